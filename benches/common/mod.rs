@@ -0,0 +1,28 @@
+use std::{
+    io::Cursor,
+    time::{Duration, Instant},
+};
+
+use machina::{
+    actor,
+    io::{Input, Output},
+    machina::{Environment, Machina},
+};
+
+// Best of `runs`, so a slow first run doesn't skew the comparison
+pub fn best<F: FnMut() -> Duration>(runs: usize, mut run: F) -> Duration {
+    (0..runs).map(|_| run()).min().unwrap()
+}
+
+// How long `entry` takes to run on a fresh machine, reading `input`
+pub fn measure(runs: usize, environment: &Environment, entry: usize, input: &str) -> Duration {
+    best(runs, || {
+        let machina = Machina::new(environment)
+            .with_input(Input::new(Cursor::new(input.to_string())))
+            .with_output(Output::buffer());
+
+        let start = Instant::now();
+        actor::run(machina, entry, vec![]).unwrap();
+        start.elapsed()
+    })
+}
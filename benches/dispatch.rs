@@ -0,0 +1,88 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    time::Duration,
+};
+
+use machina::{
+    bytecode::Module,
+    machina::Environment,
+    parser::Parser,
+};
+
+mod common;
+
+const RUNS: usize = 5;
+
+// Only one dispatch is built in at a time, so each run saves its results
+// and compares them with the last ones saved by the other dispatch:
+//
+//   cargo bench --bench dispatch
+//   cargo bench --bench dispatch --features threaded
+#[cfg(all(feature = "threaded", not(feature = "jit")))]
+const DISPATCH: (&str, &str) = ("threaded", "match");
+
+#[cfg(any(not(feature = "threaded"), feature = "jit"))]
+const DISPATCH: (&str, &str) = ("match", "threaded");
+
+fn load(path: &Path) -> HashMap<String, Duration> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let (name, nanos) = line.split_once(' ')?;
+            Some((name.to_string(), Duration::from_nanos(nanos.parse().ok()?)))
+        })
+        .collect()
+}
+
+fn main() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let results = root.join("target").join("dispatch");
+
+    let (dispatch, other) = DISPATCH;
+
+    let previous = load(&results.join(other));
+
+    let mut paths = fs::read_dir(root.join("examples"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "machina"))
+        .collect::<Vec<_>>();
+
+    paths.sort();
+
+    let mut saved = String::new();
+
+    println!("{:<12} {:>12} {:>12} {:>9}", "example", dispatch, other, "speedup");
+
+    for path in paths {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+
+        let source = fs::read_to_string(&path).unwrap();
+        let input = fs::read_to_string(path.with_extension("in")).unwrap_or_default();
+
+        let module = Parser::new(&source).parse().unwrap();
+        let entry = module.function("entrypoint").unwrap();
+
+        let Module { functions, constants, .. } = module;
+
+        let environment = Environment::new(functions, constants);
+
+        let time = common::measure(RUNS, &environment, entry, &input);
+
+        match previous.get(&name) {
+            Some(before) => {
+                let speedup = before.as_secs_f64() / time.as_secs_f64();
+                println!("{:<12} {:>12.2?} {:>12.2?} {:>8.2}x", name, time, before, speedup);
+            }
+            None => println!("{:<12} {:>12.2?} {:>12} {:>9}", name, time, "-", "-"),
+        }
+
+        saved.push_str(&format!("{} {}\n", name, time.as_nanos()));
+    }
+
+    fs::create_dir_all(&results).unwrap();
+    fs::write(results.join(dispatch), saved).unwrap();
+}
@@ -0,0 +1,44 @@
+use std::{
+    fs,
+    path::Path,
+};
+
+use machina::{
+    bytecode::Module,
+    machina::Environment,
+    parser::Parser,
+};
+
+mod common;
+
+const RUNS: usize = 3;
+
+fn main() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples").join("fibonacci.machina");
+    let source = fs::read_to_string(path).unwrap();
+
+    let module = Parser::new(&source).parse().unwrap();
+    let entry = module.function("entrypoint").unwrap();
+
+    let Module { functions, constants, .. } = module;
+
+    let packed = Environment::new(functions.clone(), constants.clone());
+
+    // Every instruction decoded from its `Operand`s, like before the packed encoding
+    let generic = functions
+        .into_iter()
+        .map(|mut function| {
+            function.generic();
+            function
+        })
+        .collect();
+
+    let generic = Environment::new(generic, constants);
+
+    let generic = common::measure(RUNS, &generic, entry, "");
+    let packed = common::measure(RUNS, &packed, entry, "");
+
+    println!("fibonacci/generic  {:>10.2?}", generic);
+    println!("fibonacci/packed   {:>10.2?}", packed);
+    println!("speedup            {:>10.2}x", generic.as_secs_f64() / packed.as_secs_f64());
+}
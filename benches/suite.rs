@@ -0,0 +1,258 @@
+use std::{
+    collections::HashMap,
+    env,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use machina::{
+    bytecode::Module,
+    machina::Environment,
+    parser::Parser,
+};
+
+mod common;
+
+const RUNS: usize = 5;
+
+// Times parsing and running every example, plus a few synthetic workloads,
+// and saves the results as JSON. Each run compares itself with the file it
+// replaces, so running it before and after a change shows the difference:
+//
+//   cargo bench --bench suite [-- <results.json>]
+//
+// The results go to `target/bench.json` by default
+const WORKLOADS: &[(&str, &str)] = &[
+    ("loop", r#"
+        @entrypoint
+          MOVE      %0, 0
+          MOVE      %1, 0
+        .L0
+          ADD       %1, 3
+          MOD       %1, 1000
+          ADD       %0, 1
+          JLT       .L0, %0, 5000000
+          RET
+    "#),
+    ("recursion", r#"
+        @entrypoint
+          MOVE      %0, 0
+        .L0
+          MOVE      %1, 2000
+          CALL      @sum, %1, %1, %1
+          ADD       %0, 1
+          JLT       .L0, %0, 500
+          RET
+
+        @sum(1) locals 2
+          JEQ       .L0, %0, 0
+          SUB       %1, %0, 1
+          CALL      @sum, %1, %1, %1
+          ADD       %0, %1
+        .L0
+          RET       %0
+    "#),
+    // There is no concatenation, so this builds its output piece by piece
+    ("strings", r#"
+        @entrypoint
+          MOVE      %0, 0
+          MOVE      %1, "the quick brown fox jumps over the lazy dog"
+          LEN       %2, %1
+        .L0
+          MOVE      %3, 0
+        .L1
+          GET       %4, %1, %3
+          PRINT     %4
+          ADD       %3, 1
+          JLT       .L1, %3, %2
+          WRITE
+          ADD       %0, 1
+          JLT       .L0, %0, 20000
+          RET
+    "#),
+    ("floats", r#"
+        @entrypoint
+          MOVE      %0, 0
+          MOVE      %1, 1.0
+        .L0
+          MUL       %1, 1.0000001
+          ADD       %1, 0.5
+          DIV       %1, 1.5
+          LT        %2, %1, 1000.0
+          ADD       %0, 1
+          JLT       .L0, %0, 2000000
+          WRITE     %1
+          RET
+    "#),
+];
+
+struct Timing {
+    name: String,
+    parse: Duration,
+    exec: Duration,
+}
+
+fn bench(name: String, source: &str, input: &str) -> Timing {
+    let parse = common::best(RUNS, || {
+        let start = Instant::now();
+        Parser::new(source).parse().unwrap();
+        start.elapsed()
+    });
+
+    let module = Parser::new(source).parse().unwrap();
+    let entry = module.function("entrypoint").unwrap();
+
+    let Module { functions, constants, .. } = module;
+
+    let environment = Environment::new(functions, constants);
+
+    let exec = common::measure(RUNS, &environment, entry, input);
+
+    Timing { name, parse, exec }
+}
+
+// One result per line. Names are the only strings, and escaping them keeps
+// it that way, so `load` only has to read back this layout, not any JSON
+fn json(results: &[Timing]) -> String {
+    let results = results
+        .iter()
+        .map(|result| {
+            format!(
+                "    {{ \"name\": \"{}\", \"parse_ns\": {}, \"exec_ns\": {} }}",
+                escape(&result.name), result.parse.as_nanos(), result.exec.as_nanos()
+            )
+        })
+        .collect::<Vec<_>>();
+
+    format!("{{\n  \"runs\": {},\n  \"results\": [\n{}\n  ]\n}}\n", RUNS, results.join(",\n"))
+}
+
+fn escape(string: &str) -> String {
+    let mut escaped = String::new();
+
+    for c in string.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+// Reads the string `escape` wrote, up to its closing quote
+fn unescape(string: &str) -> Option<String> {
+    let mut unescaped = String::new();
+    let mut chars = string.strip_prefix('"')?.chars();
+
+    loop {
+        match chars.next()? {
+            '"' => return Some(unescaped),
+            '\\' => match chars.next()? {
+                'n' => unescaped.push('\n'),
+                'r' => unescaped.push('\r'),
+                't' => unescaped.push('\t'),
+                'u' => {
+                    let code = chars.by_ref().take(4).collect::<String>();
+                    unescaped.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                c => unescaped.push(c),
+            },
+            c => unescaped.push(c),
+        }
+    }
+}
+
+// Whatever follows `"name": ` on the line
+fn field<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let key = format!("\"{}\": ", name);
+    line.find(&key).map(|start| &line[start + key.len()..])
+}
+
+fn nanos(line: &str, name: &str) -> Option<Duration> {
+    let value = field(line, name)?;
+    let end = value.find([',', ' ', '}'])?;
+    Some(Duration::from_nanos(value[..end].parse().ok()?))
+}
+
+fn load(path: &Path) -> HashMap<String, (Duration, Duration)> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let name = unescape(field(line, "name")?)?;
+            Some((name, (nanos(line, "parse_ns")?, nanos(line, "exec_ns")?)))
+        })
+        .collect()
+}
+
+fn change(before: Option<Duration>, after: Duration) -> String {
+    match before {
+        Some(before) => format!("{:+.1}%", (after.as_secs_f64() / before.as_secs_f64() - 1.0) * 100.0),
+        None => "-".into(),
+    }
+}
+
+fn main() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+    // Cargo passes `--bench` along
+    let output = env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| root.join("target").join("bench.json"));
+
+    let previous = load(&output);
+
+    let mut paths = fs::read_dir(root.join("examples"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "machina"))
+        .collect::<Vec<_>>();
+
+    paths.sort();
+
+    let mut results = vec![];
+
+    for path in paths {
+        let name = format!("examples/{}", path.file_stem().unwrap().to_string_lossy());
+        let source = fs::read_to_string(&path).unwrap();
+        let input = fs::read_to_string(path.with_extension("in")).unwrap_or_default();
+
+        results.push(bench(name, &source, &input));
+    }
+
+    for (name, source) in WORKLOADS {
+        results.push(bench(format!("workloads/{}", name), source, ""));
+    }
+
+    println!("{:<20} {:>12} {:>9} {:>12} {:>9}", "benchmark", "parse", "change", "exec", "change");
+
+    for result in results.iter() {
+        let before = previous.get(&result.name);
+
+        println!(
+            "{:<20} {:>12.2?} {:>9} {:>12.2?} {:>9}",
+            result.name,
+            result.parse,
+            change(before.map(|before| before.0), result.parse),
+            result.exec,
+            change(before.map(|before| before.1), result.exec),
+        );
+    }
+
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+
+    fs::write(&output, json(&results)).unwrap();
+
+    println!("\nSaved to {}", output.display());
+}
@@ -0,0 +1,319 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        mpsc::{channel, Sender},
+        Arc,
+        Condvar,
+        Mutex,
+    },
+    thread,
+};
+
+use crate::{
+    error::RuntimeResult,
+    machina::Machina,
+    value::Value,
+};
+
+pub type ActorId = i32;
+
+// Values are deep-copied when they cross from one instance to another,
+// no heap object is ever shared between two instances
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Value(Value),
+
+    String(String),
+
+    List(Vec<Message>),
+
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Status {
+    Running,
+    Waiting,
+    Finished,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    mailboxes: Vec<VecDeque<Message>>,
+    status: Vec<Status>,
+    running: usize,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    state: Mutex<State>,
+    signal: Condvar,
+}
+
+impl Registry {
+
+    fn register(&self) -> ActorId {
+        let mut state = self.state.lock().unwrap();
+        state.mailboxes.push(VecDeque::new());
+        state.status.push(Status::Running);
+        state.running += 1;
+        (state.mailboxes.len() - 1) as ActorId
+    }
+
+    fn send(&self, to: ActorId, message: Message) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        let index = to as usize;
+
+        if to < 0 || index >= state.mailboxes.len() {
+            return false;
+        }
+
+        match state.status[index] {
+            Status::Running => {
+                state.mailboxes[index].push_back(message);
+            }
+            Status::Waiting => {
+                state.mailboxes[index].push_back(message);
+                state.status[index] = Status::Running;
+                state.running += 1;
+            }
+            Status::Finished => {}
+        }
+
+        self.signal.notify_all();
+        true
+    }
+
+    // Blocks until a message arrives. Returns `None` once every live actor
+    // is waiting on an empty mailbox, since nobody is left to send anything
+    fn receive(&self, id: ActorId) -> Option<Message> {
+        let mut state = self.state.lock().unwrap();
+
+        let index = id as usize;
+
+        loop {
+            if let Some(message) = state.mailboxes[index].pop_front() {
+                return Some(message);
+            }
+
+            if state.status[index] == Status::Running {
+                state.status[index] = Status::Waiting;
+                state.running -= 1;
+            }
+
+            if state.running == 0 {
+                self.signal.notify_all();
+                return None;
+            }
+
+            state = self.signal.wait(state).unwrap();
+        }
+    }
+
+    fn finish(&self, id: ActorId) {
+        let mut state = self.state.lock().unwrap();
+
+        let index = id as usize;
+
+        if state.status[index] == Status::Running {
+            state.running -= 1;
+        }
+
+        state.status[index] = Status::Finished;
+        state.mailboxes[index].clear();
+
+        self.signal.notify_all();
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Actor<'a> {
+    id: ActorId,
+    registry: Arc<Registry>,
+    tasks: Sender<Task<'a>>,
+}
+
+impl<'a> Actor<'a> {
+
+    pub fn id(&self) -> ActorId {
+        self.id
+    }
+
+    pub fn spawn(&self, machina: Machina<'a>, function: usize, args: Vec<Message>) -> ActorId {
+        let id = self.registry.register();
+
+        let actor = Actor {
+            id,
+            registry: self.registry.clone(),
+            tasks: self.tasks.clone(),
+        };
+
+        let task = Task { machina: machina.attach(actor), function, args };
+
+        self.tasks.send(task).expect("The actor scheduler is gone");
+
+        id
+    }
+
+    pub fn send(&self, to: ActorId, message: Message) -> bool {
+        self.registry.send(to, message)
+    }
+
+    pub fn receive(&self) -> Option<Message> {
+        self.registry.receive(self.id)
+    }
+}
+
+#[derive(Debug)]
+pub struct Task<'a> {
+    machina: Machina<'a>,
+    function: usize,
+    args: Vec<Message>,
+}
+
+impl<'a> Task<'a> {
+
+    fn run(self) -> RuntimeResult<Message> {
+        let Task { mut machina, function, args } = self;
+
+        let result = machina.start(function, args).map(|value| machina.export(value));
+
+        // A failed actor is finished too, or the ones waiting on it never wake up
+        if let Some(actor) = machina.actor() {
+            actor.registry.finish(actor.id);
+        }
+
+        result
+    }
+}
+
+// Runs `function` on `machina` as the root actor. Every instance spawned
+// from it gets its own OS thread, and this only returns after all of them
+// have either finished or are blocked on a message that can never arrive.
+// An error in the root actor wins over the first one raised by a child
+pub fn run(machina: Machina, function: usize, args: Vec<Message>) -> RuntimeResult<Message> {
+    let registry = Arc::new(Registry::default());
+
+    let (tasks, queue) = channel();
+
+    let actor = Actor {
+        id: registry.register(),
+        registry,
+        tasks,
+    };
+
+    let root = Task { machina: machina.attach(actor), function, args };
+
+    thread::scope(|scope| {
+        let result = scope.spawn(move || root.run());
+
+        let children: Vec<_> = queue.into_iter()
+            .map(|task| scope.spawn(move || task.run()))
+            .collect();
+
+        let result = result.join().expect("The root actor panicked")?;
+
+        for child in children {
+            child.join().expect("An actor panicked")?;
+        }
+
+        Ok(result)
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        bytecode::Module,
+        machina::Environment,
+        parser::Parser,
+    };
+
+    fn environment(source: &str) -> Environment {
+        let Module { functions, constants, .. } = Parser::new(source).parse().unwrap();
+        Environment::new(functions, constants)
+    }
+
+    #[test]
+    fn send_and_receive() {
+        let environment = environment(r#"
+            @entrypoint
+              PID       %0
+              SPAWN     @double, %1, %0, %0
+              SEND      %1, 21
+              RECV      %2
+              RET       %2
+
+            @double
+              RECV      %1
+              MUL       %1, 2
+              SEND      %0, %1
+              RET
+        "#);
+
+        let result = run(Machina::new(&environment), 0, vec![]).unwrap();
+
+        assert_eq!(result, Message::Value(Value::from(42)));
+    }
+
+    #[test]
+    fn strings_are_copied() {
+        let environment = environment(r#"
+            @entrypoint
+              PID       %0
+              MOVE      %1, "hello"
+              SPAWN     @echo, %2, %0, %1
+              RECV      %2
+              RET       %2
+
+            @echo
+              SEND      %0, %1
+              RET
+        "#);
+
+        let result = run(Machina::new(&environment), 0, vec![]).unwrap();
+
+        assert_eq!(result, Message::String("hello".into()));
+    }
+
+    #[test]
+    fn idle_actors_are_stopped() {
+        let environment = environment(r#"
+            @entrypoint
+              SPAWN     @forever, %0, %0, %0
+              RET       %0
+
+            @forever
+            .L0
+              RECV      %0
+              JMP       .L0
+        "#);
+
+        let result = run(Machina::new(&environment), 0, vec![]).unwrap();
+
+        assert_eq!(result, Message::Value(Value::from(1)));
+    }
+
+    #[test]
+    fn child_errors_are_reported() {
+        let environment = environment(r#"
+            @entrypoint
+              SPAWN     @fail, %0, %0, %0
+              RET       %0
+
+            @fail
+              MOVE      %0, 1
+              DIV       %0, 0
+              RET
+        "#);
+
+        let error = run(Machina::new(&environment), 0, vec![]).unwrap_err();
+
+        assert_eq!(error.error, crate::error::MachinaError::DivisionByZero);
+        assert_eq!(error.trace[0].function, "fail");
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::HashSet;
+
+use crate::{
+    bytecode::{
+        Constant,
+        Function,
+        Instruction,
+        Module,
+        OpCode,
+        Operand,
+    },
+    error::Frame,
+    value::Value,
+};
+
+// Translates a module into a standalone C program, with one C function for
+// every function of the module. The runtime it links against is the one in
+// `aot/runtime.c`, which mirrors the values of `value.rs` and the actors of
+// `actor.rs`, so the program prints exactly what the interpreter would.
+//
+// Every instruction that can fail carries the text of its own frame, and a
+// call pushes it onto the trace of the machine for as long as it runs
+const RUNTIME: &str = include_str!("aot/runtime.c");
+
+pub fn emit(module: &Module, entry: usize) -> String {
+    let mut code = String::new();
+
+    code.push_str(&format!("/* Compiled from {} by machina {} */\n\n", name(module), env!("CARGO_PKG_VERSION")));
+    code.push_str(RUNTIME);
+    code.push('\n');
+
+    constants(&mut code, &module.constants);
+
+    for (index, _) in module.functions.iter().enumerate() {
+        code.push_str(&format!("static Value f{}(Machina *m);\n", index));
+    }
+
+    code.push_str("\nstatic const Entry entries[] = {\n");
+
+    for (index, function) in module.functions.iter().enumerate() {
+        code.push_str(&format!("    {{ f{}, {} }},\n", index, function.locals));
+    }
+
+    code.push_str("};\n\n");
+    code.push_str("static const Entry *function(size_t index)\n{\n    return &entries[index];\n}\n");
+
+    for (index, function) in module.functions.iter().enumerate() {
+        code.push('\n');
+        body(&mut code, index, function);
+    }
+
+    code.push_str(&format!("\nint main(int argc, char **argv)\n{{\n    return run(argc, argv, {});\n}}\n", entry));
+
+    code
+}
+
+fn name(module: &Module) -> String {
+    if module.file.is_empty() {
+        "a module".into()
+    } else {
+        module.file.replace("*/", "* /")
+    }
+}
+
+fn constants(code: &mut String, constants: &[Constant]) {
+    if !constants.is_empty() {
+        code.push_str(&format!("static Value constants[{}];\n\n", constants.len()));
+    }
+
+    code.push_str("static void initialize(void)\n{\n");
+
+    for (index, constant) in constants.iter().enumerate() {
+        code.push_str(&format!("    constants[{}] = {};\n", index, value(constant)));
+    }
+
+    code.push_str("}\n\n");
+}
+
+// Strings, lists and bytes are allocated once the program starts, anything
+// else is the raw value
+fn value(constant: &Constant) -> String {
+    let raw = match constant {
+        Constant::String(string) => {
+            return format!("string_value({}, {})", literal(string.as_bytes()), string.len());
+        }
+        Constant::Bytes(bytes) => {
+            return format!("bytes_value({}, {})", literal(bytes), bytes.len());
+        }
+        Constant::List(constants) if constants.is_empty() => {
+            return "list_value(0, NULL)".into();
+        }
+        Constant::List(constants) => {
+            let values = constants.iter().map(value).collect::<Vec<_>>();
+            return format!("list_value({}, (const Value[]) {{ {} }})", constants.len(), values.join(", "));
+        }
+        Constant::Number(num) => Value::from(num.value()),
+        Constant::Integer(int) => Value::from(*int),
+        Constant::Char(chr) => Value::from(*chr),
+        Constant::Boolean(boolean) => Value::from(*boolean),
+        Constant::Null => Value::null(),
+    };
+
+    format!("(Value) {:#018x}ULL", raw.get_raw())
+}
+
+// Anything but plain ASCII is escaped in octal, which unlike `\x` never
+// runs into the next character
+fn literal(bytes: &[u8]) -> String {
+    let mut literal = String::from("\"");
+
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' | b'?' => literal.push_str(&format!("\\{}", byte as char)),
+            b' ' ..= b'~' => literal.push(byte as char),
+            _ => literal.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+
+    literal.push('"');
+    literal
+}
+
+fn body(code: &mut String, index: usize, function: &Function) {
+    let len = function.instructions().len();
+
+    let targets = function.instructions()
+        .iter()
+        .filter_map(|instruction| target(instruction).map(|position| position.min(len)))
+        .collect::<HashSet<_>>();
+
+    let registers = function.instructions()
+        .iter()
+        .any(|instruction| instruction.operands.iter().any(|operand| matches!(operand, Operand::Register(_))));
+
+    code.push_str(&format!("/* @{} */\n", function.name.replace("*/", "* /")));
+    code.push_str(&format!("static Value f{}(Machina *m)\n{{\n", index));
+
+    if registers {
+        code.push_str("    const size_t bp = m->bp;\n");
+    }
+
+    if function.instructions().iter().any(|instruction| matches!(instruction.opcode, OpCode::Call | OpCode::Recv)) {
+        code.push_str("    Value value;\n");
+    }
+
+    code.push('\n');
+
+    for (ip, instruction) in function.instructions().iter().enumerate() {
+        if targets.contains(&ip) {
+            code.push_str(&format!("L{}:;\n", ip));
+        }
+
+        let site = literal(Frame::new(function, ip).to_string().as_bytes());
+
+        code.push_str(&statement(instruction, &site, len));
+    }
+
+    if targets.contains(&len) {
+        code.push_str(&format!("L{}:;\n", len));
+    }
+
+    code.push_str(&format!("    return overrun({});\n}}\n", literal(format!("@{}", function.name).as_bytes())));
+}
+
+fn target(instruction: &Instruction) -> Option<usize> {
+    match instruction.opcode {
+        OpCode::Jmp
+      | OpCode::Jt
+      | OpCode::Jf
+      | OpCode::JLt
+      | OpCode::JLe
+      | OpCode::JGt
+      | OpCode::JGe
+      | OpCode::JEq
+      | OpCode::JNe => Some(instruction.position(0) as usize),
+        _ => None,
+    }
+}
+
+fn operand(operand: Operand) -> String {
+    match operand {
+        Operand::Register(r) => format!("R({})", r),
+        Operand::Immediate(imm) => format!("(Value) {:#018x}ULL", Value::from(imm).get_raw()),
+        Operand::Constant(idx) => format!("constants[{}]", idx),
+        Operand::WideConstant(idx) => format!("constants[{}]", idx),
+        _ => "NULL_TAG".into(),
+    }
+}
+
+fn opcode_name(opcode: OpCode) -> String {
+    format!("{:?}", opcode).to_lowercase()
+}
+
+fn statement(instruction: &Instruction, site: &str, len: usize) -> String {
+    let get = |arg: usize| operand(instruction.get(arg));
+
+    let jump = |condition: String| {
+        let position = (instruction.position(0) as usize).min(len);
+        format!("    if ({}) goto L{};\n", condition, position)
+    };
+
+    let range = |first: usize, last: usize| {
+        let first = instruction.register(first);
+        let last = instruction.register(last);
+
+        if first > last {
+            Err(format!("    fail(m, {}, \"Invalid register range for `{}`\");\n", site, opcode_name(instruction.opcode)))
+        } else {
+            Ok((first, last))
+        }
+    };
+
+    match instruction.opcode {
+        OpCode::Move => format!("    {} = {};\n", get(0), get(1)),
+        OpCode::Call => match range(2, 3) {
+            Ok((first, last)) => {
+                let function = instruction.function(0);
+                format!(
+                    "    {{\n        Frame frame = {{ {}, m->frames }};\n        m->frames = &frame;\n        \
+                     value = call(m, f{}, entries[{}].locals, {}, {});\n        m->frames = frame.up;\n    }}\n    \
+                     if (m->halted)\n        return NULL_TAG;\n    {} = value;\n",
+                    site, function, function, first, last, get(1)
+                )
+            }
+            Err(error) => error,
+        },
+        OpCode::Spawn => match range(2, 3) {
+            Ok((first, last)) => format!("    {} = spawn(m, {}, {}, {});\n", get(1), instruction.function(0), first, last),
+            Err(error) => error,
+        },
+        OpCode::Send => format!("    send_value(m, {}, {}, {});\n", get(0), get(1), site),
+        OpCode::Recv => format!("    if (!receive_value(m, &value))\n        return NULL_TAG;\n    {} = value;\n", get(0)),
+        OpCode::Pid => format!("    {} = INT(m->id);\n", get(0)),
+        OpCode::Read => format!("    {} = read_value(READ);\n", get(0)),
+        OpCode::ReadLn => format!("    {} = read_value(READ_LN);\n", get(0)),
+        OpCode::ReadInt => format!("    {} = read_value(READ_INT);\n", get(0)),
+        OpCode::ReadNum => format!("    {} = read_value(READ_NUM);\n", get(0)),
+        OpCode::Len => format!("    {} = op_len(m, {}, {});\n", get(0), get(1), site),
+        OpCode::Get => format!("    {} = op_get(m, {}, {}, {});\n", get(0), get(1), get(2), site),
+        OpCode::Jmp => jump("1".into()),
+        OpCode::Jt => jump(format!("{} == TRUE_TAG", get(1))),
+        OpCode::Jf => jump(format!("{} == FLSE_TAG", get(1))),
+        OpCode::JLt => jump(format!("lt({}, {})", get(1), get(2))),
+        OpCode::JLe => jump(format!("le({}, {})", get(1), get(2))),
+        OpCode::JGt => jump(format!("gt({}, {})", get(1), get(2))),
+        OpCode::JGe => jump(format!("ge({}, {})", get(1), get(2))),
+        OpCode::JEq => jump(format!("{} == {}", get(1), get(2))),
+        OpCode::JNe => jump(format!("{} != {}", get(1), get(2))),
+        OpCode::Lt
+      | OpCode::Le
+      | OpCode::Gt
+      | OpCode::Ge
+      | OpCode::Eq
+      | OpCode::Ne
+      | OpCode::Add
+      | OpCode::Sub
+      | OpCode::Mul
+      | OpCode::Div
+      | OpCode::Mod
+      | OpCode::And
+      | OpCode::Or
+      | OpCode::Xor
+      | OpCode::Shl
+      | OpCode::Shr => {
+            let (lhs, rhs) = instruction.sources();
+            format!(
+                "    {} = op_{}(m, {}, {}, {});\n",
+                get(0), opcode_name(instruction.opcode), operand(lhs), operand(rhs), site
+            )
+        }
+        OpCode::Not => format!("    {} = op_not(m, {}, {});\n", get(0), get(0), site),
+        OpCode::Ret => format!("    return {};\n", get(0)),
+        OpCode::Write if instruction.get(0) == Operand::None => "    write_text(\"\\n\\n\");\n".into(),
+        OpCode::Write => format!("    write_value({}, \"\\n\");\n", get(0)),
+        OpCode::Print => format!("    write_value({}, \"\");\n", get(0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parser::Parser;
+
+    fn compile(source: &str) -> String {
+        let module = Parser::new(source).parse().unwrap();
+        let entry = module.function("entrypoint").unwrap();
+        emit(&module, entry)
+    }
+
+    #[test]
+    fn one_function_each() {
+        let code = compile(r#"
+            @entrypoint
+              CALL      @double, %0, %0, %0
+              RET       %0
+
+            @double
+              ADD       %0, %0
+              RET       %0
+        "#);
+
+        assert!(code.contains("static Value f0(Machina *m)\n"));
+        assert!(code.contains("static Value f1(Machina *m)\n"));
+        assert!(code.contains("value = call(m, f1, entries[1].locals, 0, 0);"));
+        assert!(code.contains("R(0) = op_add(m, R(0), R(0), \"at @double (<input>:7, instruction 0)\");"));
+        assert!(code.ends_with("return run(argc, argv, 0);\n}\n"));
+    }
+
+    #[test]
+    fn constants_are_raw_values() {
+        let code = compile(r#"
+            @entrypoint
+              MOVE      %0, "why?"
+              MOVE      %1, 2.5
+              RET       %1
+        "#);
+
+        assert!(code.contains("string_value(\"why\\?\", 4)"));
+        assert!(code.contains(&format!("(Value) {:#018x}ULL", Value::from(2.5).get_raw())));
+    }
+
+    #[test]
+    fn labels_only_on_targets() {
+        let code = compile(r#"
+            @entrypoint
+              MOVE      %0, 0
+            .L1
+              ADD       %0, 1
+              JLT       .L1, %0, 10
+              RET       %0
+        "#);
+
+        assert!(code.contains("L1:;\n"));
+        assert!(!code.contains("L0:;"));
+        assert!(code.contains("if (lt(R(0), (Value) 0xfff900000000000aULL)) goto L1;"));
+    }
+
+    #[test]
+    fn escapes_literals() {
+        assert_eq!(literal(b"a\"\\?\n\xff"), "\"a\\\"\\\\\\?\\012\\377\"");
+    }
+}
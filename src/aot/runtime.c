@@ -0,0 +1,1257 @@
+/*
+ * The runtime of a module compiled ahead of time. It mirrors the
+ * interpreter: values are NaN-boxed like in `value.rs`, every actor has its
+ * own register stack and heap, and runs on its own thread.
+ */
+
+#define _POSIX_C_SOURCE 200809L
+
+#include <math.h>
+#include <pthread.h>
+#include <setjmp.h>
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef uint64_t Value;
+
+#define TAG_MASK 0xffff000000000000ULL
+#define NAN_TAG  0xfff8000000000000ULL
+#define INT_TAG  0xfff9000000000000ULL
+#define CHR_TAG  0xfffa000000000000ULL
+#define PTR_TAG  0xfffb000000000000ULL
+#define TRUE_TAG 0xfffc000000000000ULL
+#define FLSE_TAG 0xfffd000000000000ULL
+#define NULL_TAG 0xffff000000000000ULL
+
+#define INT(i) ((Value) (INT_TAG | (uint32_t) (int32_t) (i)))
+#define BOOL(b) ((b) ? TRUE_TAG : FLSE_TAG)
+
+#define INITIAL_REG_SIZE 16
+
+static int is_num(Value v) { return v < NAN_TAG; }
+static int is_int(Value v) { return (v & TAG_MASK) == INT_TAG; }
+static int is_numeric(Value v) { return is_num(v) || is_int(v); }
+static int is_char(Value v) { return (v & TAG_MASK) == CHR_TAG; }
+static int is_ptr(Value v) { return (v & TAG_MASK) == PTR_TAG; }
+
+static int32_t get_int(Value v) { return (int32_t) (uint32_t) v; }
+static uint32_t get_char(Value v) { return (uint32_t) (v & ~CHR_TAG); }
+
+static double get_num(Value v)
+{
+    double num;
+    memcpy(&num, &v, sizeof num);
+    return num;
+}
+
+static Value from_num(double num)
+{
+    Value v;
+    memcpy(&v, &num, sizeof v);
+    return v;
+}
+
+static Value from_i64(int64_t i)
+{
+    return i >= INT32_MIN && i <= INT32_MAX ? INT(i) : from_num((double) i);
+}
+
+static double as_num(Value v)
+{
+    return is_num(v) ? get_num(v) : (double) get_int(v);
+}
+
+/* Saturates like `as i64` */
+static int64_t as_int(Value v)
+{
+    double num;
+
+    if (is_int(v))
+        return get_int(v);
+
+    num = get_num(v);
+
+    if (num != num)
+        return 0;
+    if (num >= 9223372036854775807.0)
+        return INT64_MAX;
+    if (num <= -9223372036854775807.0 - 1.0)
+        return INT64_MIN;
+
+    return (int64_t) num;
+}
+
+/* Objects */
+
+enum { OBJECT_STRING, OBJECT_LIST, OBJECT_BYTES };
+
+typedef struct {
+    int type;
+    size_t len;
+    void *data;
+} Object;
+
+static void *allocate(size_t size)
+{
+    void *memory = malloc(size ? size : 1);
+
+    if (!memory) {
+        fputs("error: Out of Memory\n", stderr);
+        exit(1);
+    }
+
+    return memory;
+}
+
+static Object *object(Value v)
+{
+    return (Object *) (uintptr_t) (v & ~PTR_TAG);
+}
+
+static Value object_value(int type, size_t len, const void *data, size_t size)
+{
+    Object *object = allocate(sizeof *object);
+
+    object->type = type;
+    object->len = len;
+    object->data = allocate(size);
+    if (size)
+        memcpy(object->data, data, size);
+
+    return PTR_TAG | (Value) (uintptr_t) object;
+}
+
+static inline Value string_value(const char *string, size_t len)
+{
+    return object_value(OBJECT_STRING, len, string, len);
+}
+
+static inline Value bytes_value(const char *bytes, size_t len)
+{
+    return object_value(OBJECT_BYTES, len, bytes, len);
+}
+
+static inline Value list_value(size_t len, const Value *values)
+{
+    return object_value(OBJECT_LIST, len, values, len * sizeof *values);
+}
+
+/* Number of chars in the UTF-8 string */
+static size_t chars(const Object *string)
+{
+    const unsigned char *bytes = string->data;
+    size_t count = 0;
+    size_t idx;
+
+    for (idx = 0; idx < string->len; idx++)
+        count += (bytes[idx] & 0xc0) != 0x80;
+
+    return count;
+}
+
+static uint32_t char_at(const Object *string, size_t index)
+{
+    const unsigned char *bytes = string->data;
+    size_t idx = 0;
+    uint32_t chr;
+    int extra;
+
+    for (; index > 0; index--)
+        for (idx++; (bytes[idx] & 0xc0) == 0x80; idx++);
+
+    chr = bytes[idx];
+    extra = chr >= 0xf0 ? 3 : chr >= 0xe0 ? 2 : chr >= 0xc0 ? 1 : 0;
+    chr &= extra ? 0x3f >> extra : 0x7f;
+
+    while (extra--)
+        chr = chr << 6 | (bytes[++idx] & 0x3f);
+
+    return chr;
+}
+
+static const char *type_name(Value v)
+{
+    if (is_num(v))
+        return "number";
+    if (is_int(v))
+        return "integer";
+    if (is_char(v))
+        return "char";
+    if (is_ptr(v)) {
+        switch (object(v)->type) {
+        case OBJECT_STRING: return "string";
+        case OBJECT_LIST: return "list";
+        default: return "bytes";
+        }
+    }
+    if (v == NULL_TAG)
+        return "null";
+    if (v == TRUE_TAG || v == FLSE_TAG)
+        return "boolean";
+    return "nan";
+}
+
+/* Formatting, the same as the `Display` of a value */
+
+typedef struct {
+    char *data;
+    size_t len;
+    size_t capacity;
+} Buffer;
+
+static void append(Buffer *buffer, const char *data, size_t len)
+{
+    if (buffer->len + len + 1 > buffer->capacity) {
+        buffer->capacity = (buffer->len + len + 1) * 2;
+        buffer->data = realloc(buffer->data, buffer->capacity);
+        if (!buffer->data) {
+            fputs("error: Out of Memory\n", stderr);
+            exit(1);
+        }
+    }
+
+    memcpy(buffer->data + buffer->len, data, len);
+    buffer->len += len;
+    buffer->data[buffer->len] = '\0';
+}
+
+static void append_string(Buffer *buffer, const char *string)
+{
+    append(buffer, string, strlen(string));
+}
+
+static void append_format(Buffer *buffer, const char *format, ...)
+{
+    char text[64];
+    va_list args;
+
+    va_start(args, format);
+    vsnprintf(text, sizeof text, format, args);
+    va_end(args);
+
+    append_string(buffer, text);
+}
+
+static void append_char(Buffer *buffer, uint32_t chr)
+{
+    char bytes[4];
+    size_t len;
+
+    if (chr < 0x80) {
+        bytes[0] = (char) chr;
+        len = 1;
+    } else if (chr < 0x800) {
+        bytes[0] = (char) (0xc0 | chr >> 6);
+        bytes[1] = (char) (0x80 | (chr & 0x3f));
+        len = 2;
+    } else if (chr < 0x10000) {
+        bytes[0] = (char) (0xe0 | chr >> 12);
+        bytes[1] = (char) (0x80 | (chr >> 6 & 0x3f));
+        bytes[2] = (char) (0x80 | (chr & 0x3f));
+        len = 3;
+    } else {
+        bytes[0] = (char) (0xf0 | chr >> 18);
+        bytes[1] = (char) (0x80 | (chr >> 12 & 0x3f));
+        bytes[2] = (char) (0x80 | (chr >> 6 & 0x3f));
+        bytes[3] = (char) (0x80 | (chr & 0x3f));
+        len = 4;
+    }
+
+    append(buffer, bytes, len);
+}
+
+/* The shortest digits that read back as the same number, never in
+   scientific notation */
+static void append_num(Buffer *buffer, double num)
+{
+    char text[32];
+    char digits[20];
+    size_t count = 0;
+    int exponent;
+    int point;
+    int precision;
+    char *cursor;
+
+    if (num != num) {
+        append_string(buffer, "NaN");
+        return;
+    }
+
+    if (signbit(num))
+        append_string(buffer, "-");
+
+    num = fabs(num);
+
+    if (isinf(num)) {
+        append_string(buffer, "inf");
+        return;
+    }
+
+    for (precision = 1; precision < 17; precision++) {
+        snprintf(text, sizeof text, "%.*e", precision - 1, num);
+        if (strtod(text, NULL) == num)
+            break;
+    }
+
+    snprintf(text, sizeof text, "%.*e", precision - 1, num);
+
+    for (cursor = text; *cursor != 'e'; cursor++)
+        if (*cursor != '.')
+            digits[count++] = *cursor;
+
+    while (count > 1 && digits[count - 1] == '0')
+        count--;
+
+    exponent = atoi(cursor + 1);
+
+    if (num == 0) {
+        count = 1;
+        exponent = 0;
+    }
+
+    point = exponent + 1;
+
+    if (point <= 0) {
+        append_string(buffer, "0.");
+        for (; point < 0; point++)
+            append_string(buffer, "0");
+        append(buffer, digits, count);
+    } else if ((size_t) point >= count) {
+        append(buffer, digits, count);
+        for (; (size_t) point > count; point--)
+            append_string(buffer, "0");
+    } else {
+        append(buffer, digits, point);
+        append_string(buffer, ".");
+        append(buffer, digits + point, count - point);
+    }
+}
+
+static void append_value(Buffer *buffer, Value v)
+{
+    Object *obj;
+    size_t idx;
+
+    if (is_num(v)) {
+        append_num(buffer, get_num(v));
+    } else if (is_int(v)) {
+        append_format(buffer, "%d", (int) get_int(v));
+    } else if (is_char(v)) {
+        append_char(buffer, get_char(v));
+    } else if (is_ptr(v)) {
+        obj = object(v);
+
+        switch (obj->type) {
+        case OBJECT_STRING:
+            append(buffer, obj->data, obj->len);
+            break;
+        case OBJECT_LIST:
+            append_string(buffer, "[");
+            for (idx = 0; idx < obj->len; idx++) {
+                if (idx > 0)
+                    append_string(buffer, ", ");
+                append_value(buffer, ((Value *) obj->data)[idx]);
+            }
+            append_string(buffer, "]");
+            break;
+        default:
+            append_string(buffer, "b\"");
+            for (idx = 0; idx < obj->len; idx++) {
+                unsigned char byte = ((unsigned char *) obj->data)[idx];
+
+                switch (byte) {
+                case '\t': append_string(buffer, "\\t"); break;
+                case '\r': append_string(buffer, "\\r"); break;
+                case '\n': append_string(buffer, "\\n"); break;
+                case '\'': append_string(buffer, "\\'"); break;
+                case '"': append_string(buffer, "\\\""); break;
+                case '\\': append_string(buffer, "\\\\"); break;
+                default:
+                    if (byte >= 0x20 && byte < 0x7f)
+                        append(buffer, (char *) &byte, 1);
+                    else
+                        append_format(buffer, "\\x%02x", byte);
+                }
+            }
+            append_string(buffer, "\"");
+        }
+    } else if (v == NULL_TAG) {
+        append_string(buffer, "null");
+    } else if (v == TRUE_TAG) {
+        append_string(buffer, "true");
+    } else if (v == FLSE_TAG) {
+        append_string(buffer, "false");
+    } else {
+        append_string(buffer, "NAN");
+    }
+}
+
+/* Machines */
+
+/* The instruction running in each active call, innermost first */
+typedef struct Frame {
+    const char *site;
+    struct Frame *up;
+} Frame;
+
+typedef struct {
+    Value *registers;
+    size_t size;
+    size_t bp;
+    size_t rp;
+    int halted;
+    int id;
+    Frame *frames;
+    jmp_buf fail;
+    char *error;
+} Machina;
+
+typedef struct {
+    Value (*run)(Machina *);
+    size_t locals;
+} Entry;
+
+/* Defined by the compiled module */
+static void initialize(void);
+static const Entry *function(size_t index);
+
+static void machina_init(Machina *m, int id)
+{
+    size_t idx;
+
+    memset(m, 0, sizeof *m);
+    m->id = id;
+    m->size = INITIAL_REG_SIZE;
+    m->registers = allocate(m->size * sizeof *m->registers);
+
+    for (idx = 0; idx < m->size; idx++)
+        m->registers[idx] = NULL_TAG;
+}
+
+/* Stops the machine with `error: ...` and the trace of every active call */
+static inline void fail(Machina *m, const char *site, const char *format, ...)
+{
+    Buffer buffer = { NULL, 0, 0 };
+    char message[256];
+    Frame *frame;
+    va_list args;
+
+    va_start(args, format);
+    vsnprintf(message, sizeof message, format, args);
+    va_end(args);
+
+    append_string(&buffer, "error: ");
+    append_string(&buffer, message);
+    append_string(&buffer, "\n    ");
+    append_string(&buffer, site);
+
+    for (frame = m->frames; frame; frame = frame->up) {
+        append_string(&buffer, "\n    ");
+        append_string(&buffer, frame->site);
+    }
+
+    m->error = buffer.data;
+    longjmp(m->fail, 1);
+}
+
+static void resize_registers(Machina *m, size_t total)
+{
+    size_t size;
+    size_t idx;
+
+    if (m->size > m->rp + total)
+        return;
+
+    size = (size_t) (1.5f * (float) m->size);
+    if (size < m->rp + total + 1)
+        size = m->rp + total + 1;
+
+    m->registers = realloc(m->registers, size * sizeof *m->registers);
+    if (!m->registers) {
+        fputs("error: Out of Memory\n", stderr);
+        exit(1);
+    }
+
+    for (idx = m->size; idx < size; idx++)
+        m->registers[idx] = NULL_TAG;
+
+    m->size = size;
+}
+
+static inline Value call(Machina *m, Value (*run)(Machina *), size_t locals, size_t first, size_t last)
+{
+    size_t count = last - first + 1;
+    size_t bp;
+    size_t rp;
+    size_t idx;
+    Value value;
+
+    resize_registers(m, count > locals ? count : locals);
+
+    for (idx = 0; idx < count; idx++)
+        m->registers[m->rp + idx] = m->registers[m->bp + first + idx];
+
+    bp = m->bp;
+    rp = m->rp;
+    m->bp = m->rp;
+    m->rp = m->bp + locals;
+
+    value = run(m);
+
+    m->rp = rp;
+    m->bp = bp;
+
+    return value;
+}
+
+/* Instructions */
+
+static void check_numeric(Machina *m, const char *site, const char *name, Value lhs, Value rhs)
+{
+    if (!is_numeric(lhs) || !is_numeric(rhs))
+        fail(m, site, "Cannot apply `%s` to %s and %s", name, type_name(lhs), type_name(rhs));
+}
+
+static void check_divisor(Machina *m, const char *site, Value lhs, Value rhs, int integer)
+{
+    integer = integer || (is_int(lhs) && is_int(rhs));
+
+    if (integer && is_numeric(rhs) && as_int(rhs) == 0)
+        fail(m, site, "Division by zero");
+}
+
+#define ARITHMETIC(name, op)                                                       \
+    static inline Value op_##name(Machina *m, Value lhs, Value rhs, const char *site)  \
+    {                                                                              \
+        if (is_int(lhs) && is_int(rhs))                                            \
+            return from_i64((int64_t) get_int(lhs) op (int64_t) get_int(rhs));     \
+        check_numeric(m, site, #name, lhs, rhs);                                   \
+        if (is_num(lhs) || is_num(rhs))                                            \
+            return from_num(as_num(lhs) op as_num(rhs));                           \
+        return from_i64(as_int(lhs) op as_int(rhs));                               \
+    }
+
+ARITHMETIC(add, +)
+ARITHMETIC(sub, -)
+ARITHMETIC(mul, *)
+
+#define COMPARISON(name, op)                                                       \
+    static inline Value op_##name(Machina *m, Value lhs, Value rhs, const char *site)  \
+    {                                                                              \
+        check_numeric(m, site, #name, lhs, rhs);                                   \
+        if (is_num(lhs) || is_num(rhs))                                            \
+            return BOOL(as_num(lhs) op as_num(rhs));                               \
+        return BOOL(as_int(lhs) op as_int(rhs));                                   \
+    }
+
+COMPARISON(lt, <)
+COMPARISON(le, <=)
+COMPARISON(gt, >)
+COMPARISON(ge, >=)
+COMPARISON(eq, ==)
+COMPARISON(ne, !=)
+
+static inline Value op_div(Machina *m, Value lhs, Value rhs, const char *site)
+{
+    check_divisor(m, site, lhs, rhs, 0);
+    check_numeric(m, site, "div", lhs, rhs);
+
+    if (is_num(lhs) || is_num(rhs))
+        return from_num(as_num(lhs) / as_num(rhs));
+
+    return from_i64(as_int(lhs) / as_int(rhs));
+}
+
+static inline Value op_mod(Machina *m, Value lhs, Value rhs, const char *site)
+{
+    int64_t divisor;
+
+    check_divisor(m, site, lhs, rhs, 1);
+    check_numeric(m, site, "mod", lhs, rhs);
+
+    divisor = as_int(rhs);
+
+    return from_i64(divisor == -1 ? 0 : as_int(lhs) % divisor);
+}
+
+#define INTEGER(name, expression)                                                  \
+    static inline Value op_##name(Machina *m, Value lhs, Value rhs, const char *site)  \
+    {                                                                              \
+        int64_t a;                                                                 \
+        int64_t b;                                                                 \
+        check_numeric(m, site, #name, lhs, rhs);                                   \
+        a = as_int(lhs);                                                           \
+        b = as_int(rhs);                                                           \
+        return from_i64(expression);                                               \
+    }
+
+INTEGER(and, a & b)
+INTEGER(or, a | b)
+INTEGER(xor, a ^ b)
+INTEGER(shl, (int64_t) ((uint64_t) a << (b & 63)))
+INTEGER(shr, a >> (b & 63))
+
+static inline Value op_not(Machina *m, Value value, const char *site)
+{
+    if (!is_numeric(value))
+        fail(m, site, "Cannot apply `not` to %s", type_name(value));
+
+    return from_i64(~as_int(value));
+}
+
+/* The ordering of values, which only exists between numbers or chars */
+static int compare(Value lhs, Value rhs, int *order)
+{
+    if (is_num(lhs) && is_numeric(rhs)) {
+        double a = as_num(lhs);
+        double b = as_num(rhs);
+
+        if (a != a || b != b)
+            return 0;
+
+        *order = (a > b) - (a < b);
+        return 1;
+    }
+
+    if (is_int(lhs) && is_numeric(rhs)) {
+        int64_t a = as_int(lhs);
+        int64_t b = as_int(rhs);
+
+        *order = (a > b) - (a < b);
+        return 1;
+    }
+
+    if (is_char(lhs) && is_char(rhs)) {
+        uint32_t a = get_char(lhs);
+        uint32_t b = get_char(rhs);
+
+        *order = (a > b) - (a < b);
+        return 1;
+    }
+
+    return 0;
+}
+
+static inline int lt(Value lhs, Value rhs) { int order; return compare(lhs, rhs, &order) && order < 0; }
+static inline int le(Value lhs, Value rhs) { int order; return compare(lhs, rhs, &order) && order <= 0; }
+static inline int gt(Value lhs, Value rhs) { int order; return compare(lhs, rhs, &order) && order > 0; }
+static inline int ge(Value lhs, Value rhs) { int order; return compare(lhs, rhs, &order) && order >= 0; }
+
+static int length(Value value, size_t *len)
+{
+    Object *obj;
+
+    if (!is_ptr(value))
+        return 0;
+
+    obj = object(value);
+    *len = obj->type == OBJECT_STRING ? chars(obj) : obj->len;
+
+    return 1;
+}
+
+static inline Value op_len(Machina *m, Value value, const char *site)
+{
+    size_t len;
+
+    if (!length(value, &len))
+        fail(m, site, "Cannot apply `len` to %s", type_name(value));
+
+    return from_i64((int64_t) len);
+}
+
+static inline Value op_get(Machina *m, Value value, Value index, const char *site)
+{
+    Object *obj;
+    size_t len;
+    int32_t idx;
+
+    if (!length(value, &len) || !is_int(index))
+        fail(m, site, "Cannot apply `get` to %s and %s", type_name(value), type_name(index));
+
+    idx = get_int(index);
+
+    if (idx < 0 || (size_t) idx >= len)
+        fail(m, site, "Index %d is out of bounds for a length of %zu", (int) idx, len);
+
+    obj = object(value);
+
+    switch (obj->type) {
+    case OBJECT_LIST: return ((Value *) obj->data)[idx];
+    case OBJECT_BYTES: return INT(((unsigned char *) obj->data)[idx]);
+    default: return CHR_TAG | char_at(obj, (size_t) idx);
+    }
+}
+
+/* Input and output, shared by every actor */
+
+static pthread_mutex_t input = PTHREAD_MUTEX_INITIALIZER;
+static pthread_mutex_t output = PTHREAD_MUTEX_INITIALIZER;
+
+static inline void write_value(Value value, const char *end)
+{
+    Buffer buffer = { NULL, 0, 0 };
+
+    append_value(&buffer, value);
+    append_string(&buffer, end);
+
+    pthread_mutex_lock(&output);
+    fwrite(buffer.data, 1, buffer.len, stdout);
+    pthread_mutex_unlock(&output);
+
+    free(buffer.data);
+}
+
+static inline void write_text(const char *text)
+{
+    pthread_mutex_lock(&output);
+    fputs(text, stdout);
+    pthread_mutex_unlock(&output);
+}
+
+/* Without the line break, or nothing once the input is over */
+static int read_line(Buffer *line)
+{
+    int chr = EOF;
+    int read = 0;
+
+    pthread_mutex_lock(&input);
+
+    while ((chr = getc(stdin)) != EOF) {
+        read = 1;
+        if (chr == '\n')
+            break;
+        append(line, (char *) &(char) { (char) chr }, 1);
+    }
+
+    pthread_mutex_unlock(&input);
+
+    if (!read)
+        return 0;
+
+    append(line, "", 0);
+
+    if (line->len > 0 && line->data[line->len - 1] == '\r')
+        line->data[--line->len] = '\0';
+
+    return 1;
+}
+
+static int is_space(char chr)
+{
+    return chr == ' ' || (chr >= '\t' && chr <= '\r');
+}
+
+/* Same grammar as `str::parse::<i64>` */
+static int parse_int(const char *text, size_t len, int64_t *value)
+{
+    uint64_t magnitude = 0;
+    int negative = 0;
+    size_t idx = 0;
+
+    if (idx < len && (text[idx] == '+' || text[idx] == '-'))
+        negative = text[idx++] == '-';
+
+    if (idx == len)
+        return 0;
+
+    for (; idx < len; idx++) {
+        uint64_t digit = (uint64_t) (text[idx] - '0');
+
+        if (text[idx] < '0' || text[idx] > '9')
+            return 0;
+
+        if (magnitude > ((uint64_t) INT64_MAX + negative - digit) / 10)
+            return 0;
+
+        magnitude = magnitude * 10 + digit;
+    }
+
+    *value = negative ? (int64_t) (0 - magnitude) : (int64_t) magnitude;
+    return 1;
+}
+
+static int matches(const char *text, size_t len, const char *word)
+{
+    size_t idx;
+
+    if (len != strlen(word))
+        return 0;
+
+    for (idx = 0; idx < len; idx++)
+        if ((text[idx] | 0x20) != word[idx])
+            return 0;
+
+    return 1;
+}
+
+/* Same grammar as `str::parse::<f64>` */
+static int parse_num(const char *text, size_t len, double *value)
+{
+    char digits[512];
+    size_t idx = 0;
+    size_t mantissa = 0;
+    int negative = 0;
+
+    if (idx < len && (text[idx] == '+' || text[idx] == '-'))
+        negative = text[idx++] == '-';
+
+    if (matches(text + idx, len - idx, "inf") || matches(text + idx, len - idx, "infinity")) {
+        *value = negative ? -INFINITY : INFINITY;
+        return 1;
+    }
+
+    if (matches(text + idx, len - idx, "nan")) {
+        *value = negative ? -NAN : NAN;
+        return 1;
+    }
+
+    for (; idx < len && text[idx] >= '0' && text[idx] <= '9'; idx++)
+        mantissa++;
+
+    if (idx < len && text[idx] == '.')
+        for (idx++; idx < len && text[idx] >= '0' && text[idx] <= '9'; idx++)
+            mantissa++;
+
+    if (mantissa == 0)
+        return 0;
+
+    if (idx < len && (text[idx] == 'e' || text[idx] == 'E')) {
+        size_t exponent = 0;
+
+        idx++;
+        if (idx < len && (text[idx] == '+' || text[idx] == '-'))
+            idx++;
+
+        for (; idx < len && text[idx] >= '0' && text[idx] <= '9'; idx++)
+            exponent++;
+
+        if (exponent == 0)
+            return 0;
+    }
+
+    if (idx != len || len >= sizeof digits)
+        return 0;
+
+    memcpy(digits, text, len);
+    digits[len] = '\0';
+
+    *value = strtod(digits, NULL);
+    return 1;
+}
+
+enum { READ, READ_LN, READ_INT, READ_NUM };
+
+static inline Value read_value(int kind)
+{
+    Buffer line = { NULL, 0, 0 };
+    const char *text;
+    size_t len;
+    int64_t integer;
+    double number;
+    Value value;
+
+    if (!read_line(&line))
+        return NULL_TAG;
+
+    text = line.data;
+    len = line.len;
+
+    while (len > 0 && is_space(*text)) {
+        text++;
+        len--;
+    }
+
+    while (len > 0 && is_space(text[len - 1]))
+        len--;
+
+    if (kind == READ_INT)
+        value = parse_int(text, len, &integer) ? from_i64(integer) : NULL_TAG;
+    else if (kind == READ_NUM)
+        value = parse_num(text, len, &number) ? from_num(number) : NULL_TAG;
+    else if (kind == READ && parse_int(text, len, &integer))
+        value = from_i64(integer);
+    else if (kind == READ && parse_num(text, len, &number))
+        value = from_num(number);
+    else
+        value = string_value(line.data, line.len);
+
+    free(line.data);
+
+    return value;
+}
+
+/* Actors */
+
+enum { MESSAGE_VALUE, MESSAGE_STRING, MESSAGE_LIST, MESSAGE_BYTES };
+
+/* A value deep-copied out of the heap of an actor */
+typedef struct Message {
+    int kind;
+    Value value;
+    size_t len;
+    void *data;
+} Message;
+
+static Message export_value(Value value)
+{
+    Message message = { MESSAGE_VALUE, 0, 0, NULL };
+    Object *obj;
+    size_t idx;
+
+    message.value = value;
+
+    if (!is_ptr(value))
+        return message;
+
+    obj = object(value);
+    message.len = obj->len;
+
+    if (obj->type == OBJECT_LIST) {
+        Message *items = allocate(obj->len * sizeof *items);
+
+        for (idx = 0; idx < obj->len; idx++)
+            items[idx] = export_value(((Value *) obj->data)[idx]);
+
+        message.kind = MESSAGE_LIST;
+        message.data = items;
+    } else {
+        message.kind = obj->type == OBJECT_STRING ? MESSAGE_STRING : MESSAGE_BYTES;
+        message.data = allocate(obj->len);
+        memcpy(message.data, obj->data, obj->len);
+    }
+
+    return message;
+}
+
+static Value import_value(Message message)
+{
+    Value *values;
+    Value value;
+    size_t idx;
+
+    switch (message.kind) {
+    case MESSAGE_STRING:
+        return string_value(message.data, message.len);
+    case MESSAGE_BYTES:
+        return bytes_value(message.data, message.len);
+    case MESSAGE_LIST:
+        values = allocate(message.len * sizeof *values);
+        for (idx = 0; idx < message.len; idx++)
+            values[idx] = import_value(((Message *) message.data)[idx]);
+        value = list_value(message.len, values);
+        free(values);
+        return value;
+    default:
+        return message.value;
+    }
+}
+
+enum { RUNNING, WAITING, FINISHED };
+
+typedef struct Node {
+    Message message;
+    struct Node *next;
+} Node;
+
+typedef struct {
+    Node *head;
+    Node *tail;
+    int status;
+} Mailbox;
+
+typedef struct {
+    Machina machina;
+    size_t function;
+    Message *args;
+    size_t count;
+    pthread_t thread;
+} Task;
+
+static struct {
+    pthread_mutex_t lock;
+    pthread_cond_t signal;
+    Mailbox *mailboxes;
+    size_t count;
+    size_t running;
+    Task **tasks;
+    size_t spawned;
+} registry = { PTHREAD_MUTEX_INITIALIZER, PTHREAD_COND_INITIALIZER, NULL, 0, 0, NULL, 0 };
+
+static int register_actor(void)
+{
+    int id;
+
+    pthread_mutex_lock(&registry.lock);
+
+    registry.mailboxes = realloc(registry.mailboxes, (registry.count + 1) * sizeof *registry.mailboxes);
+    if (!registry.mailboxes) {
+        fputs("error: Out of Memory\n", stderr);
+        exit(1);
+    }
+
+    registry.mailboxes[registry.count].head = NULL;
+    registry.mailboxes[registry.count].tail = NULL;
+    registry.mailboxes[registry.count].status = RUNNING;
+    registry.running++;
+    id = (int) registry.count++;
+
+    pthread_mutex_unlock(&registry.lock);
+
+    return id;
+}
+
+static int send_message(int to, Message message)
+{
+    Mailbox *mailbox;
+    Node *node;
+
+    pthread_mutex_lock(&registry.lock);
+
+    if (to < 0 || (size_t) to >= registry.count) {
+        pthread_mutex_unlock(&registry.lock);
+        return 0;
+    }
+
+    mailbox = &registry.mailboxes[to];
+
+    if (mailbox->status != FINISHED) {
+        node = allocate(sizeof *node);
+        node->message = message;
+        node->next = NULL;
+
+        if (mailbox->tail)
+            mailbox->tail->next = node;
+        else
+            mailbox->head = node;
+        mailbox->tail = node;
+
+        if (mailbox->status == WAITING) {
+            mailbox->status = RUNNING;
+            registry.running++;
+        }
+    }
+
+    pthread_cond_broadcast(&registry.signal);
+    pthread_mutex_unlock(&registry.lock);
+
+    return 1;
+}
+
+/* Blocks until a message arrives, or fails once every live actor is
+   waiting on an empty mailbox */
+static int receive_message(int id, Message *message)
+{
+    Mailbox *mailbox;
+    Node *node;
+
+    pthread_mutex_lock(&registry.lock);
+
+    for (;;) {
+        mailbox = &registry.mailboxes[id];
+
+        if ((node = mailbox->head)) {
+            mailbox->head = node->next;
+            if (!mailbox->head)
+                mailbox->tail = NULL;
+
+            pthread_mutex_unlock(&registry.lock);
+
+            *message = node->message;
+            free(node);
+            return 1;
+        }
+
+        if (mailbox->status == RUNNING) {
+            mailbox->status = WAITING;
+            registry.running--;
+        }
+
+        if (registry.running == 0) {
+            pthread_cond_broadcast(&registry.signal);
+            pthread_mutex_unlock(&registry.lock);
+            return 0;
+        }
+
+        pthread_cond_wait(&registry.signal, &registry.lock);
+    }
+}
+
+static void finish_actor(int id)
+{
+    Mailbox *mailbox;
+
+    pthread_mutex_lock(&registry.lock);
+
+    mailbox = &registry.mailboxes[id];
+
+    if (mailbox->status == RUNNING)
+        registry.running--;
+
+    mailbox->status = FINISHED;
+
+    while (mailbox->head) {
+        Node *node = mailbox->head;
+        mailbox->head = node->next;
+        free(node);
+    }
+
+    mailbox->tail = NULL;
+
+    pthread_cond_broadcast(&registry.signal);
+    pthread_mutex_unlock(&registry.lock);
+}
+
+static Value start(Machina *m, size_t index, const Message *args, size_t count)
+{
+    const Entry *entry = function(index);
+    size_t idx;
+
+    resize_registers(m, count);
+
+    for (idx = 0; idx < count; idx++)
+        m->registers[idx] = import_value(args[idx]);
+
+    return call(m, entry->run, entry->locals, 0, count == 0 ? 0 : count - 1);
+}
+
+static void *run_task(void *data)
+{
+    Task *task = data;
+
+    if (!setjmp(task->machina.fail))
+        start(&task->machina, task->function, task->args, task->count);
+
+    finish_actor(task->machina.id);
+
+    return NULL;
+}
+
+static inline Value spawn(Machina *m, size_t index, size_t first, size_t last)
+{
+    Task *task = allocate(sizeof *task);
+    size_t idx;
+
+    task->function = index;
+    task->count = last - first + 1;
+    task->args = allocate(task->count * sizeof *task->args);
+
+    for (idx = 0; idx < task->count; idx++)
+        task->args[idx] = export_value(m->registers[m->bp + first + idx]);
+
+    machina_init(&task->machina, register_actor());
+
+    pthread_mutex_lock(&registry.lock);
+
+    registry.tasks = realloc(registry.tasks, (registry.spawned + 1) * sizeof *registry.tasks);
+    if (!registry.tasks) {
+        fputs("error: Out of Memory\n", stderr);
+        exit(1);
+    }
+
+    registry.tasks[registry.spawned++] = task;
+
+    if (pthread_create(&task->thread, NULL, run_task, task) != 0) {
+        fputs("error: Couldn't spawn an actor\n", stderr);
+        exit(1);
+    }
+
+    pthread_mutex_unlock(&registry.lock);
+
+    return INT(task->machina.id);
+}
+
+/* Nothing once every actor is waiting, and the machine stops */
+static inline int receive_value(Machina *m, Value *value)
+{
+    Message message;
+
+    if (!receive_message(m->id, &message)) {
+        m->halted = 1;
+        return 0;
+    }
+
+    *value = import_value(message);
+    return 1;
+}
+
+static inline void send_value(Machina *m, Value to, Value value, const char *site)
+{
+    Message message = export_value(value);
+    Buffer buffer = { NULL, 0, 0 };
+
+    if (is_int(to) && send_message(get_int(to), message))
+        return;
+
+    append_value(&buffer, to);
+    fail(m, site, "Actor `%s` not found", buffer.data);
+}
+
+/* Where the interpreter would panic, running past the last instruction */
+static inline Value overrun(const char *function)
+{
+    fflush(stdout);
+    fprintf(stderr, "error: %s ran past its last instruction\n", function);
+    exit(101);
+}
+
+/* Runs the entry point as the root actor, and waits for every other one */
+static int run(int argc, char **argv, size_t entry)
+{
+    Machina root;
+    Message *args = allocate((size_t) argc * sizeof *args);
+    Task *task;
+    Value value = NULL_TAG;
+    char *error = NULL;
+    size_t idx;
+
+    initialize();
+
+    for (idx = 1; idx < (size_t) argc; idx++) {
+        int64_t integer;
+
+        if (parse_int(argv[idx], strlen(argv[idx]), &integer))
+            args[idx - 1] = export_value(from_i64(integer));
+        else
+            args[idx - 1] = export_value(string_value(argv[idx], strlen(argv[idx])));
+    }
+
+    machina_init(&root, register_actor());
+
+    if (!setjmp(root.fail))
+        value = start(&root, entry, args, (size_t) argc - 1);
+
+    finish_actor(root.id);
+
+    error = root.error;
+
+    for (idx = 0;; idx++) {
+        pthread_mutex_lock(&registry.lock);
+        task = idx < registry.spawned ? registry.tasks[idx] : NULL;
+        pthread_mutex_unlock(&registry.lock);
+
+        if (!task)
+            break;
+
+        pthread_join(task->thread, NULL);
+
+        if (!error)
+            error = task->machina.error;
+    }
+
+    fflush(stdout);
+
+    if (error) {
+        fprintf(stderr, "%s\n", error);
+        return 1;
+    }
+
+    return is_int(value) ? get_int(value) : 0;
+}
+
+/* The compiled module */
+
+#define R(n) (m->registers[bp + (n)])
@@ -1,4 +1,10 @@
-use crate::object::Number;
+use std::{collections::HashMap, convert::TryFrom};
+
+use crate::{
+    encoding::{self, Word},
+    feedback::Feedback,
+    object::Number,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OpCode {
@@ -32,12 +38,24 @@ pub enum OpCode {
     Shl,
     Shr,
     Write,
+    Print,
+    Read,
+    ReadLn,
+    ReadInt,
+    ReadNum,
+    Spawn,
+    Send,
+    Recv,
+    Pid,
+    Len,
+    Get,
 }
 
 pub type Immediate = i32;
 pub type Position  = u16;
 pub type Register  = u16;
 pub type ConstantIdx = u16;
+pub type WideConstantIdx = u32;
 pub type FunctionIdx = u16;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -48,6 +66,19 @@ pub enum Operand {
     Register(Register),
     Function(FunctionIdx),
     Constant(ConstantIdx),
+
+    // For pools too large for a `ConstantIdx`
+    WideConstant(WideConstantIdx),
+}
+
+impl Operand {
+
+    pub fn constant(index: usize) -> Operand {
+        match ConstantIdx::try_from(index) {
+            Ok(index) => Operand::Constant(index),
+            Err(_) => Operand::WideConstant(index as WideConstantIdx),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -71,6 +102,16 @@ impl Instruction {
         self.operands[arg]
     }
 
+    // The operands of a binary operation, either `dst op b` for the two
+    // address form or `a op b` for the three address one
+    #[inline(always)]
+    pub fn sources(&self) -> (Operand, Operand) {
+        match self.operands[2] {
+            Operand::None => (self.operands[0], self.operands[1]),
+            rhs => (self.operands[1], rhs),
+        }
+    }
+
     #[inline(always)]
     pub fn register(&self, arg: usize) -> Register {
         if let Operand::Register(r) = self.operands[arg] {
@@ -108,34 +149,169 @@ impl Instruction {
     }
 }
 
-#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub enum Constant {
 
     String(String),
 
     Number(Number),
+
+    Integer(i64),
+
+    Char(char),
+
+    Boolean(bool),
+
+    Null,
+
+    List(Vec<Constant>),
+
+    Bytes(Vec<u8>),
 }
 
 
 #[derive(Debug, Clone)]
 pub struct Function {
+    pub name: String,
+    pub arity: Option<u8>,
     pub locals: u8,
-    pub instructions: Vec<Instruction>
+    pub source_map: SourceMap,
+
+    // The operand types seen by each instruction, see `feedback.rs`
+    pub feedback: Feedback,
+
+    // Only changed through `edit`, so `code` is always the packed form of
+    // `instructions`, which is what actually runs
+    instructions: Vec<Instruction>,
+    code: Vec<Word>,
 }
 
 impl Function {
 
-    pub fn new(locals: u8, instructions: Vec<Instruction>) -> Function {
+    pub fn new(name: String, locals: u8, instructions: Vec<Instruction>) -> Function {
+        let code = encoding::encode(&instructions);
+        let feedback = Feedback::new(instructions.len());
+
         Function {
+            name,
+            arity: None,
             locals,
-            instructions
+            source_map: SourceMap::default(),
+            feedback,
+            instructions,
+            code,
         }
     }
+
+    pub fn with_arity(mut self, arity: Option<u8>) -> Function {
+        self.arity = arity;
+        self
+    }
+
+    pub fn with_source_map(mut self, source_map: SourceMap) -> Function {
+        self.source_map = source_map;
+        self
+    }
+
+    #[inline(always)]
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    #[inline(always)]
+    pub fn code(&self) -> &[Word] {
+        &self.code
+    }
+
+    // Encodes the instructions again once `edit` is done with them
+    pub fn edit<R>(&mut self, edit: impl FnOnce(&mut Vec<Instruction>) -> R) -> R {
+        let result = edit(&mut self.instructions);
+
+        self.code = encoding::encode(&self.instructions);
+        self.feedback = Feedback::new(self.instructions.len());
+
+        result
+    }
+
+    // Runs every instruction the slow way, decoded from its `Operand`s
+    pub fn generic(&mut self) {
+        self.code = encoding::generic(&self.instructions);
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        self.arity.is_none_or(|arity| accepts(arity, count))
+    }
+
+    // `file.machina:12` for the instruction at `index`, when it is known
+    pub fn location(&self, index: usize) -> Option<String> {
+        self.source_map
+            .line(index)
+            .map(|line| format!("{}:{}", self.source_map.file, line))
+    }
+}
+
+// A call always passes at least one register, so a function without
+// parameters is also called with a single one
+pub fn accepts(arity: u8, count: usize) -> bool {
+    count == arity as usize || (arity == 0 && count == 1)
+}
+
+// Only stores the instructions where the source line changes, and the
+// line of any other instruction is the one of the closest entry before it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    pub file: String,
+    lines: Vec<(u32, u32)>,
+}
+
+impl SourceMap {
+
+    pub fn new(file: String) -> SourceMap {
+        SourceMap {
+            file,
+            lines: vec![],
+        }
+    }
+
+    // Instructions must be added in order
+    pub fn add(&mut self, index: usize, line: usize) {
+        match self.lines.last() {
+            Some(&(_, last)) if last as usize == line => {}
+            _ => self.lines.push((index as u32, line as u32)),
+        }
+    }
+
+    pub fn entries(&self) -> &[(u32, u32)] {
+        &self.lines
+    }
+
+    pub fn line(&self, index: usize) -> Option<usize> {
+        let entry = match self.lines.binary_search_by_key(&(index as u32), |&(first, _)| first) {
+            Ok(entry) => entry,
+            Err(0) => return None,
+            Err(next) => next - 1,
+        };
+
+        Some(self.lines[entry].1 as usize)
+    }
 }
 
 
+// Function operands past the last function of the module point into
+// `externs`, the functions it calls but expects an imported module to define
 #[derive(Debug, Clone)]
 pub struct Module {
+    pub file: String,
     pub functions: Vec<Function>,
     pub constants: Vec<Constant>,
+    pub symbols: HashMap<String, usize>,
+    pub imports: Vec<String>,
+    pub externs: Vec<String>,
+}
+
+impl Module {
+
+    pub fn function(&self, name: &str) -> Option<usize> {
+        self.symbols.get(name).copied()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,466 @@
+use std::convert::TryFrom;
+
+use crate::bytecode::{
+    Instruction,
+    OpCode,
+    Operand,
+};
+
+// Every instruction is packed into a single word:
+//
+//   bits  0..8   op
+//   bits  8..16  a  (destination or left hand side register)
+//   bits 16..32  b  (jump target or function)
+//   bits 32..64  x  (source register, immediate or constant)
+//
+// The ops are specialized by the kind of their source operand, so the
+// interpreter never has to look at an `Operand`. Anything without a packed
+// form is `Generic`, and runs from the `Instruction` at the same index.
+//
+// Common pairs of instructions are also fused into superinstructions, see
+// `fuse`, and a few of those split `x` in two 16 bit halves
+pub type Word = u64;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Generic,
+    MoveRR,
+    MoveRI,
+    MoveRK,
+    AddRR,
+    AddRI,
+    AddRK,
+    SubRR,
+    SubRI,
+    SubRK,
+    MulRR,
+    MulRI,
+    MulRK,
+    AddRRR,
+    AddRRI,
+    SubRRR,
+    SubRRI,
+    MulRRR,
+    MulRRI,
+    JLtRR,
+    JLtRI,
+    JLeRR,
+    JLeRI,
+    JGtRR,
+    JGtRI,
+    JGeRR,
+    JGeRI,
+    JEqRR,
+    JEqRI,
+    JNeRR,
+    JNeRI,
+    Jmp,
+    Call,
+    Ret,
+    Add3RR,
+    Add3RI,
+    Sub3RR,
+    Sub3RI,
+    Mul3RR,
+    Mul3RI,
+    ModJEqZ,
+    ModJNeZ,
+    AddJLtRR,
+    AddJLtRI,
+    AddJLeRR,
+    AddJLeRI,
+    AddJNeRR,
+    AddJNeRI,
+    AddJmp,
+}
+
+// Indexed by the value of each op
+const OPS: [Op; 49] = [
+    Op::Generic,
+    Op::MoveRR,
+    Op::MoveRI,
+    Op::MoveRK,
+    Op::AddRR,
+    Op::AddRI,
+    Op::AddRK,
+    Op::SubRR,
+    Op::SubRI,
+    Op::SubRK,
+    Op::MulRR,
+    Op::MulRI,
+    Op::MulRK,
+    Op::AddRRR,
+    Op::AddRRI,
+    Op::SubRRR,
+    Op::SubRRI,
+    Op::MulRRR,
+    Op::MulRRI,
+    Op::JLtRR,
+    Op::JLtRI,
+    Op::JLeRR,
+    Op::JLeRI,
+    Op::JGtRR,
+    Op::JGtRI,
+    Op::JGeRR,
+    Op::JGeRI,
+    Op::JEqRR,
+    Op::JEqRI,
+    Op::JNeRR,
+    Op::JNeRI,
+    Op::Jmp,
+    Op::Call,
+    Op::Ret,
+    Op::Add3RR,
+    Op::Add3RI,
+    Op::Sub3RR,
+    Op::Sub3RI,
+    Op::Mul3RR,
+    Op::Mul3RI,
+    Op::ModJEqZ,
+    Op::ModJNeZ,
+    Op::AddJLtRR,
+    Op::AddJLtRI,
+    Op::AddJLeRR,
+    Op::AddJLeRI,
+    Op::AddJNeRR,
+    Op::AddJNeRI,
+    Op::AddJmp,
+];
+
+pub fn encode(instructions: &[Instruction]) -> Vec<Word> {
+    let mut code = instructions.iter().map(encode_instruction).collect::<Vec<_>>();
+
+    fuse(instructions, &mut code);
+
+    code
+}
+
+// Only `Generic` words, for running every instruction the slow way
+pub fn generic(instructions: &[Instruction]) -> Vec<Word> {
+    vec![pack(Op::Generic, 0, 0, 0); instructions.len()]
+}
+
+#[inline(always)]
+pub fn op(word: Word) -> Op {
+    OPS[word as u8 as usize]
+}
+
+#[inline(always)]
+pub fn a(word: Word) -> u8 {
+    (word >> 8) as u8
+}
+
+#[inline(always)]
+pub fn b(word: Word) -> u16 {
+    (word >> 16) as u16
+}
+
+#[inline(always)]
+pub fn x(word: Word) -> u32 {
+    (word >> 32) as u32
+}
+
+#[inline(always)]
+pub fn xl(word: Word) -> u16 {
+    (word >> 32) as u16
+}
+
+#[inline(always)]
+pub fn xh(word: Word) -> u16 {
+    (word >> 48) as u16
+}
+
+fn pack(op: Op, a: u8, b: u16, x: u32) -> Word {
+    op as Word | (a as Word) << 8 | (b as Word) << 16 | (x as Word) << 32
+}
+
+fn encode_instruction(instruction: &Instruction) -> Word {
+    use Operand::{Function, Position, Register};
+
+    let generic = pack(Op::Generic, 0, 0, 0);
+
+    let ops = match instruction.opcode {
+        OpCode::Move => [Op::MoveRR, Op::MoveRI, Op::MoveRK],
+        OpCode::Add  => [Op::AddRR, Op::AddRI, Op::AddRK],
+        OpCode::Sub  => [Op::SubRR, Op::SubRI, Op::SubRK],
+        OpCode::Mul  => [Op::MulRR, Op::MulRI, Op::MulRK],
+        OpCode::JLt  => [Op::JLtRR, Op::JLtRI, Op::Generic],
+        OpCode::JLe  => [Op::JLeRR, Op::JLeRI, Op::Generic],
+        OpCode::JGt  => [Op::JGtRR, Op::JGtRI, Op::Generic],
+        OpCode::JGe  => [Op::JGeRR, Op::JGeRI, Op::Generic],
+        OpCode::JEq  => [Op::JEqRR, Op::JEqRI, Op::Generic],
+        OpCode::JNe  => [Op::JNeRR, Op::JNeRI, Op::Generic],
+        _ => [Op::Generic; 3],
+    };
+
+    let packed = match (instruction.opcode, instruction.operands) {
+        (OpCode::Move, [Register(a), source, Operand::None, Operand::None])
+      | (OpCode::Add, [Register(a), source, Operand::None, Operand::None])
+      | (OpCode::Sub, [Register(a), source, Operand::None, Operand::None])
+      | (OpCode::Mul, [Register(a), source, Operand::None, Operand::None]) => {
+            register(a).zip(self::source(source)).map(|(a, (kind, x))| pack(ops[kind], a, 0, x))
+        }
+        // The three address forms, with a register on the left hand side
+        (OpCode::Add, [Register(a), Register(b), source, Operand::None])
+      | (OpCode::Sub, [Register(a), Register(b), source, Operand::None])
+      | (OpCode::Mul, [Register(a), Register(b), source, Operand::None]) if source != Operand::None => {
+            let ops = match instruction.opcode {
+                OpCode::Add => [Op::AddRRR, Op::AddRRI],
+                OpCode::Sub => [Op::SubRRR, Op::SubRRI],
+                _ => [Op::MulRRR, Op::MulRRI],
+            };
+
+            register(a)
+                .zip(self::source(source).filter(|(kind, _)| *kind < 2))
+                .map(|(a, (kind, x))| pack(ops[kind], a, b, x))
+        }
+        (OpCode::JLt, [Position(b), Register(a), source, Operand::None])
+      | (OpCode::JLe, [Position(b), Register(a), source, Operand::None])
+      | (OpCode::JGt, [Position(b), Register(a), source, Operand::None])
+      | (OpCode::JGe, [Position(b), Register(a), source, Operand::None])
+      | (OpCode::JEq, [Position(b), Register(a), source, Operand::None])
+      | (OpCode::JNe, [Position(b), Register(a), source, Operand::None]) => {
+            register(a).zip(self::source(source)).map(|(a, (kind, x))| pack(ops[kind], a, b, x))
+        }
+        (OpCode::Jmp, [Position(b), Operand::None, Operand::None, Operand::None]) => {
+            Some(pack(Op::Jmp, 0, b, 0))
+        }
+        (OpCode::Call, [Function(b), Register(a), Register(first), Register(last)]) => {
+            register(a).map(|a| pack(Op::Call, a, b, first as u32 | (last as u32) << 16))
+        }
+        (OpCode::Ret, [Register(a), Operand::None, Operand::None, Operand::None]) => {
+            register(a).map(|a| pack(Op::Ret, a, 0, 0))
+        }
+        _ => None,
+    };
+
+    packed
+        .filter(|word| op(*word) != Op::Generic)
+        .unwrap_or(generic)
+}
+
+// The first word of a fused pair does the work of both instructions and then
+// skips the second one. That word is left as it was, so jumping straight to
+// it still works, and positions and source lines don't change
+fn fuse(instructions: &[Instruction], code: &mut [Word]) {
+    for (index, pair) in instructions.windows(2).enumerate() {
+        if let Some(word) = fuse_pair(&pair[0], &pair[1]) {
+            code[index] = word;
+        }
+    }
+}
+
+fn fuse_pair(first: &Instruction, second: &Instruction) -> Option<Word> {
+    use Operand::{Immediate, Position, Register};
+
+    match (first.opcode, first.operands, second.opcode, second.operands) {
+        // `MOVE %a, %b` and `ADD %a, x` is `%a = %b + x`, unless `x` is the
+        // `%a` the move just overwrote
+        (
+            OpCode::Move, [Register(a), Register(b), Operand::None, Operand::None],
+            OpCode::Add | OpCode::Sub | OpCode::Mul, [Register(dst), source, Operand::None, Operand::None],
+        ) if a == dst && source != Register(a) => {
+            let ops = match second.opcode {
+                OpCode::Add => [Op::Add3RR, Op::Add3RI],
+                OpCode::Sub => [Op::Sub3RR, Op::Sub3RI],
+                _ => [Op::Mul3RR, Op::Mul3RI],
+            };
+
+            match self::source(source)? {
+                (kind, x) if kind < 2 => Some(pack(ops[kind], register(a)?, b, x)),
+                _ => None,
+            }
+        }
+        // `MOD %a, k` and then a jump on whether `%a` is zero
+        (
+            OpCode::Mod, [Register(a), Immediate(divisor), Operand::None, Operand::None],
+            OpCode::JEq | OpCode::JNe, [Position(b), Register(lhs), Immediate(0), Operand::None],
+        ) if a == lhs && divisor != 0 => {
+            let op = if second.opcode == OpCode::JEq { Op::ModJEqZ } else { Op::ModJNeZ };
+            Some(pack(op, register(a)?, b, divisor as u32))
+        }
+        (
+            OpCode::Add, [Register(a), Immediate(step), Operand::None, Operand::None],
+            OpCode::Jmp, [Position(b), Operand::None, Operand::None, Operand::None],
+        ) => {
+            Some(pack(Op::AddJmp, register(a)?, b, step as u32))
+        }
+        // Increment and compare, with the step in `xl` and the right hand side
+        // of the comparison in `xh`
+        (
+            OpCode::Add, [Register(a), Immediate(step), Operand::None, Operand::None],
+            OpCode::JLt | OpCode::JLe | OpCode::JNe, [Position(b), Register(lhs), source, Operand::None],
+        ) if a == lhs => {
+            let ops = match second.opcode {
+                OpCode::JLt => [Op::AddJLtRR, Op::AddJLtRI],
+                OpCode::JLe => [Op::AddJLeRR, Op::AddJLeRI],
+                _ => [Op::AddJNeRR, Op::AddJNeRI],
+            };
+
+            let step = i16::try_from(step).ok()? as u16;
+
+            let (kind, rhs) = match source {
+                Register(register) => (0, register),
+                Immediate(immediate) => (1, i16::try_from(immediate).ok()? as u16),
+                _ => return None,
+            };
+
+            Some(pack(ops[kind], register(a)?, b, step as u32 | (rhs as u32) << 16))
+        }
+        _ => None,
+    }
+}
+
+fn register(register: u16) -> Option<u8> {
+    u8::try_from(register).ok()
+}
+
+// The kind of a source operand (register, immediate or constant) and its value
+fn source(operand: Operand) -> Option<(usize, u32)> {
+    match operand {
+        Operand::Register(register) => Some((0, register as u32)),
+        Operand::Immediate(immediate) => Some((1, immediate as u32)),
+        Operand::Constant(constant) => Some((2, constant as u32)),
+        Operand::WideConstant(constant) => Some((2, constant)),
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parser::Parser;
+
+    fn ops(source: &str) -> Vec<Op> {
+        let module = Parser::new(source).parse().unwrap();
+        module.functions[0].code().iter().map(|word| op(*word)).collect()
+    }
+
+    #[test]
+    fn specialize_by_operand_kind() {
+        assert_eq!(ops(r#"
+            @entrypoint
+              MOVE      %0, %1
+              MOVE      %0, 1
+              MOVE      %0, "k"
+              ADD       %0, %1
+              SUB       %0, 1
+              MUL       %0, 1.5
+              ADD       %0, %1, %2
+              SUB       %0, %1, 2
+              MUL       %0, 2, %1
+              JLE       .L0, %0, 1
+              JEQ       .L0, %0, %1
+              JEQ       .L0, %0, "k"
+              DIV       %0, %1
+            .L0
+              CALL      @entrypoint, %0, %0, %1
+              JMP       .L0
+              RET       %0
+              RET
+        "#), vec![
+            Op::MoveRR,
+            Op::MoveRI,
+            Op::MoveRK,
+            Op::AddRR,
+            Op::SubRI,
+            Op::MulRK,
+            Op::AddRRR,
+            Op::SubRRI,
+            Op::Generic,
+            Op::JLeRI,
+            Op::JEqRR,
+            Op::Generic,
+            Op::Generic,
+            Op::Call,
+            Op::Jmp,
+            Op::Ret,
+            Op::Generic,
+        ]);
+    }
+
+    #[test]
+    fn pack_fields() {
+        let module = Parser::new(r#"
+            @entrypoint
+              CALL      @entrypoint, %2, %3, %4
+              JGT       .L0, %1, -5
+            .L0
+              RET       %0
+        "#).parse().unwrap();
+
+        let code = module.functions[0].code();
+
+        assert_eq!((op(code[0]), a(code[0]), b(code[0]), x(code[0])), (Op::Call, 2, 0, 3 | 4 << 16));
+        assert_eq!((op(code[1]), a(code[1]), b(code[1]), x(code[1]) as i32), (Op::JGtRI, 1, 2, -5));
+    }
+
+    #[test]
+    fn fuse_superinstructions() {
+        let module = Parser::new(r#"
+            @entrypoint
+              MOVE      %1, %0
+              SUB       %1, 1
+              MOVE      %2, %0
+              ADD       %2, %2
+              MOVE      %3, %0
+              MOD       %3, 3
+              JEQ       .L0, %3, 0
+            .L0
+              ADD       %0, 1
+              JLE       .L0, %0, 100
+              ADD       %0, 70000
+              JLT       .L0, %0, %1
+              ADD       %0, 1
+              JMP       .L0
+        "#).parse().unwrap();
+
+        let code = module.functions[0].code();
+
+        assert_eq!(code.iter().map(|word| op(*word)).collect::<Vec<_>>(), vec![
+            Op::Sub3RI,
+            Op::SubRI,
+            Op::MoveRR,
+            Op::AddRR,
+            Op::MoveRR,
+            Op::ModJEqZ,
+            Op::JEqRI,
+            Op::AddJLeRI,
+            Op::JLeRI,
+            Op::AddRI,
+            Op::JLtRR,
+            Op::AddJmp,
+            Op::Jmp,
+        ]);
+
+        assert_eq!((a(code[0]), b(code[0]), x(code[0])), (1, 0, 1));
+        assert_eq!((a(code[5]), b(code[5]), x(code[5])), (3, 7, 3));
+        assert_eq!((a(code[7]), b(code[7]), xl(code[7]), xh(code[7])), (0, 7, 1, 100));
+    }
+
+    #[test]
+    fn encode_again_after_edits() {
+        let mut module = Parser::new(r#"
+            @entrypoint
+              MOVE      %0, 1
+              RET       %0
+        "#).parse().unwrap();
+
+        let function = &mut module.functions[0];
+
+        function.edit(|instructions| instructions[0].operands[1] = Operand::Register(1));
+
+        assert_eq!(op(function.code()[0]), Op::MoveRR);
+        assert_eq!(function.code().len(), function.instructions().len());
+    }
+
+    #[test]
+    fn ops_match_their_table() {
+        for (index, op) in OPS.iter().enumerate() {
+            assert_eq!(*op as usize, index);
+        }
+    }
+}
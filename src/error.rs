@@ -2,8 +2,12 @@ use std::error::Error;
 use std::fmt;
 use std::fmt::{Display};
 
+use crate::bytecode::{Function, Register};
+
 pub type Result<T> = ::std::result::Result<T, MachinaError>;
 
+pub type RuntimeResult<T> = ::std::result::Result<T, RuntimeError>;
+
 #[derive(Debug, Clone)]
 pub struct Diagnostics {
     errors: Vec<(MachinaError, Option<ErrorMetaData>)>,
@@ -18,17 +22,31 @@ impl Diagnostics {
         self.errors.is_empty()
     }
 
-    pub fn emit(&self) {
+    pub fn errors(&self) -> impl Iterator<Item = &MachinaError> {
+        self.errors.iter().map(|(error, _)| error)
+    }
+
+    pub fn emit(&self, file: &str, source: &str) {
+        eprint!("{}", self.render(file, source));
+    }
+
+    pub fn render(&self, file: &str, source: &str) -> String {
+        let mut output = String::new();
+
         for (error, meta) in self.errors.iter() {
-            match meta {
-                Some(meta) => {
-                    eprintln!("ERROR [{}]: {}", meta.line, error)
-                }
-                None => {
-                    eprintln!("ERROR: {}", error)
+            output.push_str(&format!("error: {}\n", error));
+
+            if let Some(meta) = meta {
+                output.push_str(&meta.span.render(file, source));
+
+                for (note, span) in meta.notes.iter() {
+                    output.push_str(&format!("note: {}\n", note));
+                    output.push_str(&span.render(file, source));
                 }
             }
         }
+
+        output
     }
 
     pub fn report<T>(&mut self, error: MachinaError) -> Result<T> {
@@ -37,8 +55,12 @@ impl Diagnostics {
         Err(error)
     }
 
-    pub fn report_with_line<T>(&mut self, error: MachinaError, line: usize) -> Result<T> {
-        let meta = Some(ErrorMetaData { line });
+    pub fn report_with_span<T>(&mut self, error: MachinaError, span: Span) -> Result<T> {
+        self.report_with_notes(error, span, vec![])
+    }
+
+    pub fn report_with_notes<T>(&mut self, error: MachinaError, span: Span, notes: Vec<(String, Span)>) -> Result<T> {
+        let meta = Some(ErrorMetaData { span, notes });
         self.errors.push((error.clone(), meta));
 
         Err(error)
@@ -47,7 +69,44 @@ impl Diagnostics {
 
 #[derive(Debug, Clone)]
 pub struct ErrorMetaData {
-    line: usize
+    span: Span,
+    notes: Vec<(String, Span)>,
+}
+
+// Lines and columns start at 1, `start` and `end` are byte offsets
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+
+    pub fn render(&self, file: &str, source: &str) -> String {
+        let start = self.start.min(source.len());
+
+        let line_start = source[..start].rfind('\n').map_or(0, |idx| idx + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |idx| start + idx);
+
+        let text = &source[line_start..line_end];
+
+        let indent = source[line_start..start]
+            .chars()
+            .map(|chr| if chr == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+
+        let width = source[start..self.end.clamp(start, line_end)].chars().count().max(1);
+
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        format!(
+            "{gutter}--> {}:{}:{}\n{gutter} |\n{} | {}\n{gutter} | {}{}\n",
+            file, self.line, self.column, number, text, indent, "^".repeat(width), gutter = gutter
+        )
+    }
 }
 
 
@@ -56,11 +115,38 @@ pub enum MachinaError {
     UnterminatedString,
     Expected(String, String),
     InvalidCharacter(char),
+    InvalidChar(String),
     InvalidInstruction(String),
     TargetNotFound(String),
     FunctionNotFound(String),
+    MacroNotTerminated(String),
+    MacroAlreadyDefined(String),
+    MacroArguments(String, usize, usize),
+    MacroRecursion(String),
+    ConstantNotFound(String),
+    ConstantAlreadyDefined(String),
+    InvalidExpression(String),
+    RegisterAliasNotFound(String),
+    InvalidCount(String),
+    ArityMismatch(String, u8, usize),
+    TooFewLocals(String, u8, usize),
+    RegisterAliasConflict(String, Register),
+    DuplicateFunction(String, String),
+    UnresolvedFunction(String, String),
+    TooManyFunctions(usize),
+    EntryPointNotFound(String),
+    InvalidObject(String),
     InvalidRegister(String),
 
+    InvalidOperands(String, String, String),
+    InvalidOperand(String, String),
+    InvalidRange(String),
+    IndexOutOfBounds(i64, usize),
+    DivisionByZero,
+    ActorNotFound(String),
+    NoActorSystem(String),
+    Io(String),
+
     OutOfMemory,
 }
 
@@ -76,6 +162,9 @@ impl Display for MachinaError {
             MachinaError::InvalidCharacter(chr) => {
                 write!(f, "Invalid character `{}`", chr)
             }
+            MachinaError::InvalidChar(chr) => {
+                write!(f, "Invalid char literal `'{}'`", chr)
+            }
             MachinaError::InvalidInstruction(ins) => {
                 write!(f, "Invalid instruction `{}`", ins)
             }
@@ -85,9 +174,84 @@ impl Display for MachinaError {
             MachinaError::FunctionNotFound(function) => {
                 write!(f, "Function with name `{}` not found", function)
             }
+            MachinaError::MacroNotTerminated(name) => {
+                write!(f, "Macro `{}` is missing its `%endmacro`", name)
+            }
+            MachinaError::MacroAlreadyDefined(name) => {
+                write!(f, "Macro `{}` is already defined", name)
+            }
+            MachinaError::MacroArguments(name, expected, found) => {
+                write!(f, "Macro `{}` takes {} arguments but {} were given", name, expected, found)
+            }
+            MachinaError::MacroRecursion(name) => {
+                write!(f, "Too many expansions of macro `{}`, is it recursive?", name)
+            }
+            MachinaError::ConstantNotFound(name) => {
+                write!(f, "Constant with name `{}` not found", name)
+            }
+            MachinaError::ConstantAlreadyDefined(name) => {
+                write!(f, "Constant `{}` is already defined", name)
+            }
+            MachinaError::InvalidExpression(reason) => {
+                write!(f, "Invalid constant expression: {}", reason)
+            }
+            MachinaError::InvalidCount(found) => {
+                write!(f, "Expected a count between 0 and 255, found `{}`", found)
+            }
+            MachinaError::ArityMismatch(function, arity, count) => {
+                write!(f, "Function `@{}` takes {} arguments but {} were given", function, arity, count)
+            }
+            MachinaError::TooFewLocals(function, locals, used) => {
+                write!(f, "Function `@{}` declares {} locals but uses {}", function, locals, used)
+            }
+            MachinaError::RegisterAliasNotFound(name) => {
+                write!(f, "Register alias `%{}` not found", name)
+            }
+            MachinaError::RegisterAliasConflict(name, register) => {
+                write!(f, "Register alias `%{}` is already bound to `%{}`", name, register)
+            }
+            MachinaError::DuplicateFunction(function, file) => {
+                write!(f, "Function with name `{}` is defined again in `{}`", function, file)
+            }
+            MachinaError::UnresolvedFunction(function, file) => {
+                write!(f, "Function with name `{}` used in `{}` is not defined by any module", function, file)
+            }
+            MachinaError::TooManyFunctions(count) => {
+                write!(f, "Linked module has {} functions, but at most {} are supported", count, u16::MAX as usize + 1)
+            }
+            MachinaError::InvalidObject(reason) => {
+                write!(f, "Invalid object file: {}", reason)
+            }
+            MachinaError::EntryPointNotFound(function) => {
+                write!(f, "Entry point `@{}` not found", function)
+            }
             MachinaError::InvalidRegister(register) => {
                 write!(f, "Invalid register `%{}`", register)
             }
+            MachinaError::InvalidOperands(operation, lhs, rhs) => {
+                write!(f, "Cannot apply `{}` to {} and {}", operation, lhs, rhs)
+            }
+            MachinaError::InvalidOperand(operation, operand) => {
+                write!(f, "Cannot apply `{}` to {}", operation, operand)
+            }
+            MachinaError::InvalidRange(operation) => {
+                write!(f, "Invalid register range for `{}`", operation)
+            }
+            MachinaError::IndexOutOfBounds(index, len) => {
+                write!(f, "Index {} is out of bounds for a length of {}", index, len)
+            }
+            MachinaError::DivisionByZero => {
+                write!(f, "Division by zero")
+            }
+            MachinaError::ActorNotFound(actor) => {
+                write!(f, "Actor `{}` not found", actor)
+            }
+            MachinaError::NoActorSystem(operation) => {
+                write!(f, "`{}` requires an actor system", operation)
+            }
+            MachinaError::Io(error) => {
+                write!(f, "I/O error: {}", error)
+            }
             MachinaError::OutOfMemory => {
                 write!(f, "Out of Memory")
             }
@@ -96,3 +260,61 @@ impl Display for MachinaError {
 }
 
 impl Error for MachinaError { }
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    pub index: usize,
+    pub location: Option<String>,
+}
+
+impl Frame {
+
+    pub fn new(function: &Function, index: usize) -> Frame {
+        Frame {
+            function: function.name.clone(),
+            index,
+            location: function.location(index),
+        }
+    }
+}
+
+impl Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.location {
+            Some(location) => {
+                write!(f, "at @{} ({}, instruction {})", self.function, location, self.index)
+            }
+            None => {
+                write!(f, "at @{} (instruction {})", self.function, self.index)
+            }
+        }
+    }
+}
+
+// The trace is innermost first, one frame for each active call
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub error: MachinaError,
+    pub trace: Vec<Frame>,
+}
+
+impl From<MachinaError> for RuntimeError {
+    fn from(error: MachinaError) -> RuntimeError {
+        RuntimeError { error, trace: vec![] }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: {}", self.error)?;
+
+        for frame in self.trace.iter() {
+            write!(f, "\n    {}", frame)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for RuntimeError { }
@@ -0,0 +1,101 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::value::Value;
+
+// The types of the operands each instruction has seen so far, as a set of
+// these. Arithmetic keeps a fast path for each single kind, and anything it
+// hasn't seen before takes the generic path and gets recorded. Besides
+// picking the fast path, it is the type profile of the function for a
+// compiler to specialize on.
+//
+// Every arithmetic and comparison instruction the interpreter runs is
+// recorded, packed and fused ones included. Native code doesn't record, so
+// the profile is the one gathered before the jit compiled the function
+pub const INTEGERS: u8 = 1;
+pub const NUMBERS: u8 = 2;
+pub const MIXED: u8 = 4;
+pub const OTHER: u8 = 8;
+
+// Lives in the function, so every actor running it shares the same one
+#[derive(Default)]
+pub struct Feedback(Vec<AtomicU8>);
+
+impl Feedback {
+
+    pub fn new(len: usize) -> Feedback {
+        Feedback((0..len).map(|_| AtomicU8::new(0)).collect())
+    }
+
+    #[inline(always)]
+    pub fn get(&self, index: usize) -> u8 {
+        self.0[index].load(Ordering::Relaxed)
+    }
+
+    // Only writes when it sees something new, which soon stops happening
+    #[inline(always)]
+    pub fn record(&self, index: usize, types: u8) {
+        let slot = &self.0[index];
+
+        if slot.load(Ordering::Relaxed) & types != types {
+            slot.fetch_or(types, Ordering::Relaxed);
+        }
+    }
+}
+
+pub fn types(lhs: Value, rhs: Value) -> u8 {
+    match (lhs.is_int(), lhs.is_num(), rhs.is_int(), rhs.is_num()) {
+        (true, _, true, _) => INTEGERS,
+        (_, true, _, true) => NUMBERS,
+        (true, _, _, true) | (_, true, true, _) => MIXED,
+        _ => OTHER,
+    }
+}
+
+impl Clone for Feedback {
+    fn clone(&self) -> Feedback {
+        Feedback(self.0.iter().map(|slot| AtomicU8::new(slot.load(Ordering::Relaxed))).collect())
+    }
+}
+
+impl fmt::Debug for Feedback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|slot| slot.load(Ordering::Relaxed)))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operand_types() {
+        assert_eq!(types(Value::from(1), Value::from(2)), INTEGERS);
+        assert_eq!(types(Value::from(1.5), Value::from(2.5)), NUMBERS);
+        assert_eq!(types(Value::from(1), Value::from(2.5)), MIXED);
+        assert_eq!(types(Value::from(1.5), Value::from(2)), MIXED);
+        assert_eq!(types(Value::from(1), Value::null()), OTHER);
+        assert_eq!(types(Value::from('a'), Value::from('b')), OTHER);
+    }
+
+    #[test]
+    fn records_every_type_seen() {
+        let feedback = Feedback::new(2);
+
+        assert_eq!(feedback.get(0), 0);
+
+        feedback.record(0, INTEGERS);
+        feedback.record(0, INTEGERS);
+        assert_eq!(feedback.get(0), INTEGERS);
+
+        feedback.record(0, NUMBERS);
+        assert_eq!(feedback.get(0), INTEGERS | NUMBERS);
+        assert_eq!(feedback.get(1), 0);
+
+        assert_eq!(feedback.clone().get(0), INTEGERS | NUMBERS);
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    sync::{Arc, Mutex},
+};
+
+// Both are shared between every instance spawned from the same program,
+// so actors read from and write to the same place as their parent
+#[derive(Clone)]
+pub struct Input(Arc<Mutex<dyn BufRead + Send>>);
+
+impl Input {
+
+    pub fn new<R: BufRead + Send + 'static>(reader: R) -> Input {
+        Input(Arc::new(Mutex::new(reader)))
+    }
+
+    pub fn stdin() -> Input {
+        Input::new(BufReader::new(io::stdin()))
+    }
+
+    pub fn empty() -> Input {
+        Input::new(io::empty())
+    }
+
+    pub fn read_line(&self) -> Option<String> {
+        let mut line = String::new();
+
+        match self.0.lock().unwrap().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => {
+                if line.ends_with('\n') {
+                    line.pop();
+                }
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+                Some(line)
+            }
+        }
+    }
+}
+
+impl Default for Input {
+    fn default() -> Input {
+        Input::stdin()
+    }
+}
+
+impl fmt::Debug for Input {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Input")
+    }
+}
+
+enum Sink {
+    Stdout(io::Stdout),
+    Buffer(Vec<u8>),
+    Writer(Box<dyn Write + Send>),
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Stdout(stdout) => stdout.write(buf),
+            Sink::Buffer(buffer) => buffer.write(buf),
+            Sink::Writer(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Stdout(stdout) => stdout.flush(),
+            Sink::Buffer(buffer) => buffer.flush(),
+            Sink::Writer(writer) => writer.flush(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Output(Arc<Mutex<Sink>>);
+
+impl Output {
+
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Output {
+        Output::from_sink(Sink::Writer(Box::new(writer)))
+    }
+
+    pub fn stdout() -> Output {
+        Output::from_sink(Sink::Stdout(io::stdout()))
+    }
+
+    pub fn buffer() -> Output {
+        Output::from_sink(Sink::Buffer(vec![]))
+    }
+
+    fn from_sink(sink: Sink) -> Output {
+        Output(Arc::new(Mutex::new(sink)))
+    }
+
+    pub fn write(&self, args: fmt::Arguments) -> io::Result<()> {
+        self.0.lock().unwrap().write_fmt(args)
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+
+    // Everything written so far, when the output is a buffer
+    pub fn contents(&self) -> Option<String> {
+        match &*self.0.lock().unwrap() {
+            Sink::Buffer(buffer) => Some(String::from_utf8_lossy(buffer).into_owned()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Output {
+    fn default() -> Output {
+        Output::stdout()
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Output")
+    }
+}
@@ -0,0 +1,817 @@
+use std::{
+    convert::TryFrom,
+    mem,
+    os::raw::{c_int, c_void},
+    ptr,
+};
+
+use crate::{
+    bytecode::{
+        Function,
+        Instruction,
+        OpCode,
+        Operand,
+    },
+    error::RuntimeError,
+    value::Value,
+};
+
+// A baseline compiler from the unpacked instructions of a function into
+// x86-64 code. Only integers take the native paths, and any guard failing
+// (a float, an overflow, a division by zero...) deopts back to the
+// interpreter at the instruction that failed, which runs it again the slow
+// way. Everything without a native form calls back into the interpreter
+// for that single instruction, and goes on natively from there. That
+// includes calls, so it is loops that get faster, not recursion.
+//
+// While running, the native code keeps
+//
+//   rbx  the `Context`
+//   r12  the first register of the frame
+//   r13  the tag of the integers
+//   r14  the constants
+//   r15  the table with the native offset of every instruction
+
+// Calls or loop iterations before a function gets compiled
+pub const THRESHOLD: u32 = 1000;
+
+// Deopts before a function is left to the interpreter for good
+const MAX_DEOPTS: u32 = 8;
+
+// What the native code returns besides the instruction to deopt at
+pub const RETURN: i64 = -1;
+pub const ERROR: i64 = -2;
+
+pub type Entry = unsafe extern "sysv64" fn(*mut Context, usize) -> i64;
+
+// Runs a single instruction in the interpreter, returning either the next
+// one, `RETURN` or `ERROR`
+pub type Step = extern "sysv64" fn(*mut Context, usize) -> i64;
+
+// The first three fields are read by the native code, see the offsets below
+#[repr(C)]
+#[derive(Debug)]
+pub struct Context {
+    pub registers: *mut Value,
+    pub constants: *const Value,
+    pub value: Value,
+    pub machina: *mut (),
+    pub function: *const Function,
+    pub ip: usize,
+    pub error: Option<RuntimeError>,
+}
+
+const REGISTERS: i32 = 0;
+const CONSTANTS: i32 = 8;
+const VALUE: i32 = 16;
+
+impl Context {
+
+    pub fn new(registers: *mut Value, constants: *const Value, machina: *mut (), function: &Function) -> Context {
+        Context {
+            registers,
+            constants,
+            value: Value::null(),
+            machina,
+            function,
+            ip: 0,
+            error: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    calls: u32,
+    loops: u32,
+    deopts: u32,
+    code: Option<Code>,
+    disabled: bool,
+}
+
+impl State {
+
+    fn entry(&mut self, threshold: u32, function: &Function, step: Step) -> Option<Entry> {
+        if self.disabled {
+            return None;
+        }
+
+        if self.code.is_none() {
+            if self.calls < threshold && self.loops < threshold {
+                return None;
+            }
+
+            match compile(function, step) {
+                Some(code) => self.code = Some(code),
+                None => {
+                    self.disabled = true;
+                    return None;
+                }
+            }
+        }
+
+        self.code.as_ref().map(Code::entry)
+    }
+}
+
+#[derive(Debug)]
+pub struct Jit {
+    threshold: Option<u32>,
+    states: Vec<State>,
+}
+
+impl Jit {
+
+    // Nothing is ever compiled without a threshold
+    pub fn new(threshold: Option<u32>) -> Jit {
+        Jit {
+            threshold,
+            states: vec![],
+        }
+    }
+
+    pub fn threshold(&self) -> Option<u32> {
+        self.threshold
+    }
+
+    pub fn compiled(&self) -> usize {
+        self.states.iter().filter(|state| state.code.is_some()).count()
+    }
+
+    fn state(&mut self, index: usize) -> &mut State {
+        if index >= self.states.len() {
+            self.states.resize_with(index + 1, State::default);
+        }
+
+        &mut self.states[index]
+    }
+
+    // Whether a loop of the function just got hot enough to leave the interpreter
+    pub fn looped(&mut self, index: usize) -> bool {
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => return false,
+        };
+
+        let state = self.state(index);
+
+        if state.disabled {
+            return false;
+        }
+
+        state.loops = state.loops.saturating_add(1);
+        state.loops >= threshold
+    }
+
+    // Counts a call of the function, returning its native code if it has
+    // any by now
+    pub fn call(&mut self, index: usize, function: &Function, step: Step) -> Option<Entry> {
+        let threshold = self.threshold?;
+
+        let state = self.state(index);
+        state.calls = state.calls.saturating_add(1);
+        state.entry(threshold, function, step)
+    }
+
+    // The native code of the function, compiling it once it is hot
+    pub fn entry(&mut self, index: usize, function: &Function, step: Step) -> Option<Entry> {
+        let threshold = self.threshold?;
+
+        self.state(index).entry(threshold, function, step)
+    }
+
+    // The code is kept even once disabled, since a frame further up the
+    // stack may still be running it
+    pub fn deopt(&mut self, index: usize) {
+        let state = self.state(index);
+
+        state.deopts += 1;
+        state.loops = 0;
+
+        if state.deopts >= MAX_DEOPTS {
+            state.disabled = true;
+        }
+    }
+}
+
+// Executable memory holding the code of one function, which starts with its entry
+#[derive(Debug)]
+pub struct Code {
+    memory: *mut u8,
+    size: usize,
+}
+
+// Nothing else ever points into the memory
+unsafe impl Send for Code {}
+
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const PROT_EXEC: c_int = 4;
+const MAP_PRIVATE: c_int = 2;
+const MAP_ANONYMOUS: c_int = 0x20;
+const PAGE: usize = 4096;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+impl Code {
+
+    fn new(bytes: &[u8]) -> Option<Code> {
+        let size = bytes.len().div_ceil(PAGE) * PAGE;
+
+        unsafe {
+            let memory = mmap(ptr::null_mut(), size, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+
+            if memory as isize == -1 {
+                return None;
+            }
+
+            let code = Code { memory: memory as *mut u8, size };
+
+            ptr::copy_nonoverlapping(bytes.as_ptr(), code.memory, bytes.len());
+
+            if mprotect(memory, size, PROT_READ | PROT_EXEC) != 0 {
+                return None;
+            }
+
+            Some(code)
+        }
+    }
+
+    fn entry(&self) -> Entry {
+        unsafe { mem::transmute::<*mut u8, Entry>(self.memory) }
+    }
+}
+
+impl Drop for Code {
+
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.memory as *mut c_void, self.size);
+        }
+    }
+}
+
+pub fn compile(function: &Function, step: Step) -> Option<Code> {
+    let mut assembler = Assembler::new(function.instructions().len());
+
+    assembler.prologue();
+
+    for (ip, instruction) in function.instructions().iter().enumerate() {
+        assembler.label(ip);
+
+        if !assembler.native(ip, instruction) {
+            assembler.step(ip, step);
+        }
+    }
+
+    // Running past the last instruction is left to the interpreter too
+    assembler.label(function.instructions().len());
+    assembler.deopt(function.instructions().len());
+
+    Code::new(&assembler.finish())
+}
+
+const RAX: u8 = 0;
+const RCX: u8 = 1;
+const RDX: u8 = 2;
+const RBX: u8 = 3;
+const RBP: u8 = 5;
+const RSI: u8 = 6;
+const RDI: u8 = 7;
+const R12: u8 = 12;
+const R13: u8 = 13;
+const R14: u8 = 14;
+const R15: u8 = 15;
+
+// Condition codes
+const O: u8 = 0x0;
+const E: u8 = 0x4;
+const NE: u8 = 0x5;
+const S: u8 = 0x8;
+const L: u8 = 0xc;
+const GE: u8 = 0xd;
+const LE: u8 = 0xe;
+const G: u8 = 0xf;
+
+// `op r/m, reg` forms
+const ADD: u8 = 0x01;
+const OR: u8 = 0x09;
+const SUB: u8 = 0x29;
+const CMP: u8 = 0x39;
+const TEST: u8 = 0x85;
+const MOV: u8 = 0x89;
+
+// `Value::from(i32)` puts the integer in the low half, under this tag
+const INT_TAG: u32 = 0xfff9_0000;
+
+struct Assembler {
+    code: Vec<u8>,
+
+    // Native offset of every instruction, and of the end of the function
+    labels: Vec<usize>,
+
+    // Every `rel32` to patch, with the instruction it jumps to
+    jumps: Vec<(usize, usize)>,
+
+    // Same, but resuming in the interpreter at that instruction
+    deopts: Vec<(usize, usize)>,
+
+    exits: Vec<usize>,
+    dispatches: Vec<usize>,
+    table: usize,
+}
+
+impl Assembler {
+
+    fn new(instructions: usize) -> Assembler {
+        Assembler {
+            code: vec![],
+            labels: vec![0; instructions + 1],
+            jumps: vec![],
+            deopts: vec![],
+            exits: vec![],
+            dispatches: vec![],
+            table: 0,
+        }
+    }
+
+    fn label(&mut self, ip: usize) {
+        self.labels[ip] = self.code.len();
+    }
+
+    fn native(&mut self, ip: usize, instruction: &Instruction) -> bool {
+        use Operand::{Position, Register};
+
+        match (instruction.opcode, instruction.operands) {
+            (OpCode::Move, [Register(dst), source, Operand::None, Operand::None]) if loadable(source) => {
+                self.value(RAX, source);
+                self.store(dst, RAX);
+            }
+            (OpCode::Add, [Register(dst), ..])
+          | (OpCode::Sub, [Register(dst), ..])
+          | (OpCode::Mul, [Register(dst), ..])
+          | (OpCode::Div, [Register(dst), ..])
+          | (OpCode::Mod, [Register(dst), ..]) if integers(instruction) => {
+                let (lhs, rhs) = instruction.sources();
+
+                self.integer(RAX, lhs, ip);
+                self.integer(RDX, rhs, ip);
+                self.arithmetic(instruction.opcode, ip);
+
+                self.alu(OR, true, RAX, R13);
+                self.store(dst, RAX);
+            }
+            (OpCode::Lt, [Register(dst), ..])
+          | (OpCode::Le, [Register(dst), ..])
+          | (OpCode::Gt, [Register(dst), ..])
+          | (OpCode::Ge, [Register(dst), ..])
+          | (OpCode::Eq, [Register(dst), ..])
+          | (OpCode::Ne, [Register(dst), ..]) if integers(instruction) => {
+                let (lhs, rhs) = instruction.sources();
+
+                self.integer(RAX, lhs, ip);
+                self.integer(RDX, rhs, ip);
+                self.alu(CMP, false, RAX, RDX);
+
+                // false - condition << 48 is true when the condition holds
+                self.emit(&[0x0f, 0x90 | condition(instruction.opcode), 0xc0]);
+                self.emit(&[0x0f, 0xb6, 0xc0]);
+                self.shift(4, RAX, 48);
+                self.mov_imm64(RCX, Value::from(false).get_raw());
+                self.alu(SUB, true, RCX, RAX);
+                self.store(dst, RCX);
+            }
+            (OpCode::JLt, [Position(target), lhs, rhs, Operand::None])
+          | (OpCode::JLe, [Position(target), lhs, rhs, Operand::None])
+          | (OpCode::JGt, [Position(target), lhs, rhs, Operand::None])
+          | (OpCode::JGe, [Position(target), lhs, rhs, Operand::None])
+          | (OpCode::JEq, [Position(target), lhs, rhs, Operand::None])
+          | (OpCode::JNe, [Position(target), lhs, rhs, Operand::None]) if integer(lhs) && integer(rhs) => {
+                self.integer(RAX, lhs, ip);
+                self.integer(RDX, rhs, ip);
+                self.alu(CMP, false, RAX, RDX);
+
+                let rel = self.jcc(condition(instruction.opcode));
+                self.jumps.push((rel, target as usize));
+            }
+            (OpCode::Jt, [Position(target), Register(register), Operand::None, Operand::None])
+          | (OpCode::Jf, [Position(target), Register(register), Operand::None, Operand::None]) => {
+                let value = Value::from(instruction.opcode == OpCode::Jt);
+
+                self.load(RAX, R12, register as i32 * 8);
+                self.mov_imm64(RCX, value.get_raw());
+                self.alu(CMP, true, RAX, RCX);
+
+                let rel = self.jcc(E);
+                self.jumps.push((rel, target as usize));
+            }
+            (OpCode::Jmp, [Position(target), ..]) => {
+                let rel = self.jmp();
+                self.jumps.push((rel, target as usize));
+            }
+            (OpCode::Ret, [Register(register), ..]) => {
+                self.load(RAX, R12, register as i32 * 8);
+                self.store_to(RBX, VALUE, RAX);
+                self.mov_imm64(RAX, RETURN as u64);
+
+                let rel = self.jmp();
+                self.exits.push(rel);
+            }
+            _ => return false,
+        }
+
+        true
+    }
+
+    // Calls back into the interpreter for the instruction at `ip`
+    fn step(&mut self, ip: usize, step: Step) {
+        self.alu(MOV, true, RDI, RBX);
+        self.mov_imm32(RSI, ip as u32);
+        self.mov_imm64(RAX, step as usize as u64);
+        self.emit(&[0xff, 0xd0]);
+
+        // A return or an error
+        self.alu(TEST, true, RAX, RAX);
+        let rel = self.jcc(S);
+        self.exits.push(rel);
+
+        // Calls may have moved the registers
+        self.load(R12, RBX, REGISTERS);
+
+        // And anything may have jumped
+        self.emit(&[0x48, 0x81, 0xf8]);
+        self.emit(&(ip as u32 + 1).to_le_bytes());
+        let rel = self.jcc(NE);
+        self.dispatches.push(rel);
+    }
+
+    fn deopt(&mut self, ip: usize) {
+        self.mov_imm32(RAX, ip as u32);
+        let rel = self.jmp();
+        self.exits.push(rel);
+    }
+
+    // Loads the raw value of `operand`
+    fn value(&mut self, register: u8, operand: Operand) {
+        match operand {
+            Operand::Register(source) => self.load(register, R12, source as i32 * 8),
+            Operand::Immediate(immediate) => self.mov_imm64(register, Value::from(immediate).get_raw()),
+            Operand::Constant(index) => self.load(register, R14, index as i32 * 8),
+            Operand::WideConstant(index) => self.load(register, R14, index as i32 * 8),
+            _ => unreachable!(),
+        }
+    }
+
+    // Loads the integer in `operand` into the low half of `register`, deopting
+    // when it isn't one
+    fn integer(&mut self, register: u8, operand: Operand, ip: usize) {
+        match operand {
+            Operand::Immediate(immediate) => self.mov_imm32(register, immediate as u32),
+            Operand::Register(source) => {
+                self.load(register, R12, source as i32 * 8);
+                self.alu(MOV, true, RCX, register);
+                self.shift(5, RCX, 32);
+                self.emit(&[0x81, 0xf9]);
+                self.emit(&INT_TAG.to_le_bytes());
+
+                let rel = self.jcc(NE);
+                self.deopts.push((rel, ip));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // `eax op edx` into `eax`, deopting whenever the result would not be an
+    // integer the interpreter can produce
+    fn arithmetic(&mut self, opcode: OpCode, ip: usize) {
+        match opcode {
+            OpCode::Add => self.alu(ADD, false, RAX, RDX),
+            OpCode::Sub => self.alu(SUB, false, RAX, RDX),
+            OpCode::Mul => self.emit(&[0x0f, 0xaf, 0xc2]),
+            _ => {
+                // Division by zero is an error the interpreter raises
+                self.alu(TEST, false, RDX, RDX);
+                let rel = self.jcc(E);
+                self.deopts.push((rel, ip));
+
+                // Widened, so `i32::MIN / -1` can't trap
+                self.emit(&[0x48, 0x63, 0xc0]);
+                self.emit(&[0x48, 0x63, 0xca]);
+                self.emit(&[0x48, 0x99]);
+                self.emit(&[0x48, 0xf7, 0xf9]);
+
+                if opcode == OpCode::Div {
+                    self.emit(&[0x48, 0x63, 0xc8]);
+                    self.alu(CMP, true, RCX, RAX);
+                    let rel = self.jcc(NE);
+                    self.deopts.push((rel, ip));
+                    self.alu(MOV, false, RAX, RAX);
+                } else {
+                    self.alu(MOV, false, RAX, RDX);
+                }
+
+                return;
+            }
+        }
+
+        let rel = self.jcc(O);
+        self.deopts.push((rel, ip));
+    }
+
+    fn prologue(&mut self) {
+        for &register in &[RBP, RBX, R12, R13, R14, R15] {
+            self.rex(false, 0, register);
+            self.emit(&[0x50 | register & 7]);
+        }
+
+        // Keeps the stack aligned for calls
+        self.emit(&[0x48, 0x83, 0xec, 0x08]);
+
+        self.alu(MOV, true, RBX, RDI);
+        self.load(R12, RBX, REGISTERS);
+        self.load(R14, RBX, CONSTANTS);
+        self.mov_imm64(R13, (INT_TAG as u64) << 32);
+
+        // lea r15, [rip + table]
+        self.emit(&[0x4c, 0x8d, 0x3d]);
+        self.table = self.code.len();
+        self.emit(&[0; 4]);
+
+        self.alu(MOV, true, RAX, RSI);
+        let rel = self.jmp();
+        self.dispatches.push(rel);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        // Jumps to the instruction in rax
+        let dispatch = self.code.len();
+        self.emit(&[0x49, 0x63, 0x04, 0x87]);
+        self.alu(ADD, true, RAX, R15);
+        self.emit(&[0xff, 0xe0]);
+
+        let mut deopts = self.deopts.clone();
+        deopts.sort_by_key(|&(_, ip)| ip);
+
+        let mut stub = (usize::MAX, 0);
+
+        for (rel, ip) in deopts {
+            if stub.0 != ip {
+                stub = (ip, self.code.len());
+                self.deopt(ip);
+            }
+
+            self.patch(rel, stub.1);
+        }
+
+        let epilogue = self.code.len();
+        self.emit(&[0x48, 0x83, 0xc4, 0x08]);
+
+        for &register in &[R15, R14, R13, R12, RBX, RBP] {
+            self.rex(false, 0, register);
+            self.emit(&[0x58 | register & 7]);
+        }
+
+        self.emit(&[0xc3]);
+
+        while !self.code.len().is_multiple_of(4) {
+            self.emit(&[0xcc]);
+        }
+
+        let table = self.code.len();
+
+        for &label in &self.labels.clone() {
+            self.emit(&(label as i32 - table as i32).to_le_bytes());
+        }
+
+        self.patch(self.table, table);
+
+        for (rel, target) in self.jumps.clone() {
+            self.patch(rel, self.labels[target.min(self.labels.len() - 1)]);
+        }
+
+        for rel in self.dispatches.clone() {
+            self.patch(rel, dispatch);
+        }
+
+        for rel in self.exits.clone() {
+            self.patch(rel, epilogue);
+        }
+
+        self.code
+    }
+
+    fn patch(&mut self, rel: usize, target: usize) {
+        let offset = target as i32 - (rel as i32 + 4);
+        self.code[rel..rel + 4].copy_from_slice(&offset.to_le_bytes());
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn rex(&mut self, wide: bool, reg: u8, rm: u8) {
+        let rex = 0x40 | (wide as u8) << 3 | (reg >> 3) << 2 | rm >> 3;
+
+        if rex != 0x40 {
+            self.emit(&[rex]);
+        }
+    }
+
+    // Always with a 32 bit displacement
+    fn memory(&mut self, reg: u8, base: u8, displacement: i32) {
+        self.emit(&[0x80 | (reg & 7) << 3 | base & 7]);
+
+        if base & 7 == 4 {
+            self.emit(&[0x24]);
+        }
+
+        self.emit(&displacement.to_le_bytes());
+    }
+
+    fn load(&mut self, register: u8, base: u8, displacement: i32) {
+        self.rex(true, register, base);
+        self.emit(&[0x8b]);
+        self.memory(register, base, displacement);
+    }
+
+    fn store_to(&mut self, base: u8, displacement: i32, register: u8) {
+        self.rex(true, register, base);
+        self.emit(&[0x89]);
+        self.memory(register, base, displacement);
+    }
+
+    fn store(&mut self, destination: u16, register: u8) {
+        self.store_to(R12, destination as i32 * 8, register);
+    }
+
+    fn mov_imm64(&mut self, register: u8, immediate: u64) {
+        self.rex(true, 0, register);
+        self.emit(&[0xb8 | register & 7]);
+        self.emit(&immediate.to_le_bytes());
+    }
+
+    fn mov_imm32(&mut self, register: u8, immediate: u32) {
+        self.rex(false, 0, register);
+        self.emit(&[0xb8 | register & 7]);
+        self.emit(&immediate.to_le_bytes());
+    }
+
+    fn alu(&mut self, op: u8, wide: bool, destination: u8, source: u8) {
+        self.rex(wide, source, destination);
+        self.emit(&[op, 0xc0 | (source & 7) << 3 | destination & 7]);
+    }
+
+    fn shift(&mut self, extension: u8, register: u8, amount: u8) {
+        self.rex(true, 0, register);
+        self.emit(&[0xc1, 0xc0 | extension << 3 | register & 7, amount]);
+    }
+
+    fn jcc(&mut self, condition: u8) -> usize {
+        self.emit(&[0x0f, 0x80 | condition]);
+        self.emit(&[0; 4]);
+        self.code.len() - 4
+    }
+
+    fn jmp(&mut self) -> usize {
+        self.emit(&[0xe9]);
+        self.emit(&[0; 4]);
+        self.code.len() - 4
+    }
+}
+
+fn condition(opcode: OpCode) -> u8 {
+    match opcode {
+        OpCode::Lt | OpCode::JLt => L,
+        OpCode::Le | OpCode::JLe => LE,
+        OpCode::Gt | OpCode::JGt => G,
+        OpCode::Ge | OpCode::JGe => GE,
+        OpCode::Eq | OpCode::JEq => E,
+        OpCode::Ne | OpCode::JNe => NE,
+        _ => unreachable!(),
+    }
+}
+
+// Whether a source can only ever be an integer or deopt
+fn integer(operand: Operand) -> bool {
+    matches!(operand, Operand::Register(_) | Operand::Immediate(_))
+}
+
+fn integers(instruction: &Instruction) -> bool {
+    let (lhs, rhs) = instruction.sources();
+    integer(lhs) && integer(rhs)
+}
+
+fn loadable(operand: Operand) -> bool {
+    match operand {
+        Operand::Register(_) | Operand::Immediate(_) | Operand::Constant(_) => true,
+        Operand::WideConstant(index) => i32::try_from(index as u64 * 8).is_ok(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parser::Parser;
+
+    extern "sysv64" fn unreachable_step(_: *mut Context, _: usize) -> i64 {
+        unreachable!()
+    }
+
+    fn function(source: &str) -> Function {
+        Parser::new(source).parse().unwrap().functions.remove(0)
+    }
+
+    fn run(function: &Function, registers: &mut [Value], ip: usize) -> (i64, Value) {
+        let code = compile(function, unreachable_step).unwrap();
+
+        let mut context = Context::new(registers.as_mut_ptr(), ptr::null(), ptr::null_mut(), function);
+
+        let exit = unsafe { (code.entry())(&mut context, ip) };
+
+        (exit, context.value)
+    }
+
+    #[test]
+    fn integer_loop() {
+        let function = function(r#"
+            @sum
+              MOVE      %1, 0
+            .L0
+              JGT       .L1, %0, 10
+              ADD       %1, %0
+              ADD       %0, 1
+              JMP       .L0
+            .L1
+              LT        %2, %1, 100
+              JGE       .L2, %1, 100
+              MOD       %1, 7
+              DIV       %1, %1, 2
+            .L2
+              RET       %1
+        "#);
+
+        let mut registers = [Value::from(1), Value::null(), Value::null()];
+
+        assert_eq!(run(&function, &mut registers, 0), (RETURN, Value::from(3)));
+        assert_eq!(registers[2], Value::from(true));
+    }
+
+    #[test]
+    fn deopt_on_guards() {
+        let function = function(r#"
+            @guards
+              ADD       %0, %1
+              MUL       %0, %0, 65536
+              DIV       %0, %1
+              RET       %0
+        "#);
+
+        // Not an integer
+        let mut registers = [Value::from(1.5), Value::from(1)];
+        assert_eq!(run(&function, &mut registers, 0).0, 0);
+
+        // Overflows
+        let mut registers = [Value::from(65535), Value::from(1)];
+        assert_eq!(run(&function, &mut registers, 0).0, 1);
+        assert_eq!(registers[0], Value::from(65536));
+
+        // Divides by zero
+        let mut registers = [Value::from(0), Value::from(0)];
+        assert_eq!(run(&function, &mut registers, 0).0, 2);
+
+        // Can be entered anywhere
+        let mut registers = [Value::from(8), Value::from(2)];
+        assert_eq!(run(&function, &mut registers, 2), (RETURN, Value::from(4)));
+    }
+
+    #[test]
+    fn compiles_once_hot() {
+        let function = function(r#"
+            @hot
+              RET       %0
+        "#);
+
+        let mut jit = Jit::new(Some(2));
+
+        assert!(jit.call(0, &function, unreachable_step).is_none());
+        assert!(jit.call(0, &function, unreachable_step).is_some());
+        assert_eq!(jit.compiled(), 1);
+
+        for _ in 0..MAX_DEOPTS {
+            jit.deopt(0);
+        }
+
+        assert!(jit.entry(0, &function, unreachable_step).is_none());
+        assert!(!jit.looped(0));
+
+        let mut jit = Jit::new(None);
+        assert!(jit.call(0, &function, unreachable_step).is_none());
+        assert!(!jit.looped(0));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{error::{MachinaError, Result}};
+use crate::{error::{MachinaError, Result, Span}};
 
 use std::{fmt, str::Chars};
 
@@ -34,10 +34,55 @@ fn get_instruction(key: &str) -> Option<Token> {
         "shl"  => Some(Token::Shl),
         "shr"  => Some(Token::Shr),
         "write"  => Some(Token::Write),
+        "print"  => Some(Token::Print),
+        "read"   => Some(Token::Read),
+        "readln" => Some(Token::ReadLn),
+        "readint" => Some(Token::ReadInt),
+        "readnum" => Some(Token::ReadNum),
+        "spawn"  => Some(Token::Spawn),
+        "send"   => Some(Token::Send),
+        "recv"   => Some(Token::Recv),
+        "pid"    => Some(Token::Pid),
+        "len"    => Some(Token::Len),
+        "get"    => Some(Token::Get),
+        "import" => Some(Token::Import),
+        "true"   => Some(Token::True),
+        "false"  => Some(Token::False),
+        "null"   => Some(Token::Null),
         _ => None,
     }
 }
 
+// Strings keep their escape sequences, but a char literal is decoded into
+// the single character it stands for
+fn unescape(value: &str) -> Option<char> {
+    let mut chars = value.chars();
+
+    let chr = match (chars.next()?, chars.next()) {
+        ('\\', Some(escaped)) => {
+            match escaped {
+                '\\' | '\'' | '"' => escaped,
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'a' => '\x07',
+                'b' => '\x08',
+                'f' => '\x0c',
+                'v' => '\x0b',
+                '0' => '\0',
+                _ => return None,
+            }
+        }
+        (chr, None) => chr,
+        _ => return None,
+    };
+
+    match chars.next() {
+        Some(_) => None,
+        None => Some(chr),
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Lexer<'s> {
     source: &'s str,
@@ -45,6 +90,9 @@ pub struct Lexer<'s> {
     curr: Option<char>,
     peek: Option<char>,
     line: usize,
+    column: usize,
+    offset: usize,
+    span: Span,
     value: Option<String>,
 }
 
@@ -55,7 +103,10 @@ impl<'s> Lexer<'s> {
             chars: source.chars(),
             curr: None,
             peek: None,
-            line: 0,
+            line: 1,
+            column: 1,
+            offset: 0,
+            span: Span::default(),
             value: None,
         };
         lexer.initialize();
@@ -67,13 +118,14 @@ impl<'s> Lexer<'s> {
         self.next_char();
 
         while self.curr == Some('\n') {
-            self.line += 1;
             self.next_char();
         }
     }
 
     fn next_token(&mut self) -> Result<Token> {
         loop {
+            let (start, line, column) = (self.offset, self.line, self.column);
+
             let token = match self.curr {
                 Some(' ')
               | Some('\t')
@@ -100,11 +152,18 @@ impl<'s> Lexer<'s> {
                 Some('-') | Some('+') if self.is_number(self.peek) => {
                     self.number(true)
                 }
+                Some('+') => self.single(Token::Plus),
+                Some('-') => self.single(Token::Minus),
+                Some('*') => self.single(Token::Star),
+                Some('/') => self.single(Token::Slash),
+                Some('=') => self.single(Token::Equal),
                 Some('"') => {
                     self.string()
                 }
+                Some('\'') => {
+                    self.char()
+                }
                 Some('\n') => {
-                    self.line += 1;
                     self.single(Token::EOL)
                 }
                 Some(',') => self.single(Token::Comma),
@@ -119,11 +178,14 @@ impl<'s> Lexer<'s> {
                     continue;
                 },
                 Some(invalid) => {
+                    self.next_char();
                     Err(MachinaError::InvalidCharacter(invalid))
                 }
                 None => Ok(Token::EOF)
             };
 
+            self.span = Span { line, column, start, end: self.offset };
+
             return token;
         }
     }
@@ -135,6 +197,18 @@ impl<'s> Lexer<'s> {
 
     fn next_char(&mut self) -> Option<char> {
         let curr = self.curr;
+
+        if let Some(chr) = curr {
+            self.offset += chr.len_utf8();
+
+            if chr == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+
         self.curr = self.peek;
         self.peek = self.chars.next();
         curr
@@ -158,7 +232,8 @@ impl<'s> Lexer<'s> {
         if let Some(instruction) = get_instruction(&value[..].to_lowercase()) {
             Ok(instruction)
         } else {
-            Err(MachinaError::InvalidInstruction(value.into()))
+            self.value = Some(value);
+            Ok(Token::Identifier)
         }
     }
 
@@ -171,6 +246,17 @@ impl<'s> Lexer<'s> {
             value.push(self.next_char().unwrap());
         }
 
+        match (kind, &value[..]) {
+            (Token::Register, "macro") => return Ok(Token::Macro),
+            (Token::Register, "endmacro") => return Ok(Token::EndMacro),
+            (Token::Label, "define") => return Ok(Token::Define),
+            (Token::Label, "equ") => return Ok(Token::Equ),
+            (Token::Label, "reg") => return Ok(Token::Reg),
+            (Token::Label, "data") => return Ok(Token::Data),
+            (Token::Label, "extern") => return Ok(Token::Extern),
+            _ => {}
+        }
+
         self.value = Some(value.into());
 
         Ok(kind)
@@ -201,9 +287,30 @@ impl<'s> Lexer<'s> {
     }
 
     fn string(&mut self) -> Result<Token> {
+        let value = self.quoted('"').ok_or(MachinaError::UnterminatedString)?;
+
+        self.value = Some(value);
+
+        Ok(Token::String)
+    }
+
+    fn char(&mut self) -> Result<Token> {
+        let value = self.quoted('\'').unwrap_or_default();
+
+        match unescape(&value) {
+            Some(chr) => {
+                self.value = Some(chr.to_string());
+                Ok(Token::Char)
+            }
+            None => Err(MachinaError::InvalidChar(value)),
+        }
+    }
+
+    // Reads up to the closing `quote`, or returns `None` if the line ends first
+    fn quoted(&mut self, quote: char) -> Option<String> {
         let mut value = String::new();
 
-        self.next_char(); // "
+        self.next_char(); // opening quote
 
         loop {
             match self.curr {
@@ -226,12 +333,12 @@ impl<'s> Lexer<'s> {
                         None => value.push('\\'),
                     }
                 }
-                Some('\"') => {
+                Some(chr) if chr == quote => {
                     break;
                 }
                 Some('\n')
               | None => {
-                    return Err(MachinaError::UnterminatedString);
+                    return None;
                 }
 
                 _ => {}
@@ -240,11 +347,9 @@ impl<'s> Lexer<'s> {
             value.push(self.next_char().unwrap());
         }
 
-        self.next_char(); // "
-
-        self.value = Some(value.into());
+        self.next_char(); // closing quote
 
-        Ok(Token::String)
+        Some(value)
     }
 
     fn comment(&mut self) {
@@ -266,6 +371,11 @@ impl<'s> Lexer<'s> {
         self.line
     }
 
+    // Location of the last token returned, including the invalid ones
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
     pub fn take_value(&mut self) -> Option<String> {
         self.value.take()
     }
@@ -289,6 +399,11 @@ pub enum Token {
     LBracket, // [
     RBracket, // ]
     Comma,    // ,
+    Plus,     // +
+    Minus,    // -
+    Star,     // *
+    Slash,    // /
+    Equal,    // =
 
     // instructions
     Call,
@@ -321,17 +436,44 @@ pub enum Token {
     Shl,
     Shr,
     Write,
+    Print,
+    Read,
+    ReadLn,
+    ReadInt,
+    ReadNum,
+    Spawn,
+    Send,
+    Recv,
+    Pid,
+    Len,
+    Get,
+
+    // directives
+    Import,
+    Macro,
+    EndMacro,
+    Define,
+    Equ,
+    Reg,
+    Data,
+    Extern,
 
     // values
     String,
     Number,
+    Char,
+    True,
+    False,
+    Null,
     Label,
     Function,
     Register,
+    Identifier,
 
     // descriptor
     Operand,
     Instruction,
+    Invalid,
 
     // others
     EOL,
@@ -348,6 +490,11 @@ impl fmt::Display for Token {
             Token::LBracket => write!(f, "["),
             Token::RBracket => write!(f, "]"),
             Token::Comma => write!(f, ","),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Star => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::Equal => write!(f, "="),
             Token::Call => write!(f, "call"),
             Token::Ret => write!(f, "ret"),
             Token::Move => write!(f, "move"),
@@ -378,13 +525,38 @@ impl fmt::Display for Token {
             Token::Shl => write!(f, "shl"),
             Token::Shr => write!(f, "shr"),
             Token::Write => write!(f, "write"),
+            Token::Print => write!(f, "print"),
+            Token::Read => write!(f, "read"),
+            Token::ReadLn => write!(f, "readln"),
+            Token::ReadInt => write!(f, "readint"),
+            Token::ReadNum => write!(f, "readnum"),
+            Token::Spawn => write!(f, "spawn"),
+            Token::Send => write!(f, "send"),
+            Token::Recv => write!(f, "recv"),
+            Token::Pid => write!(f, "pid"),
+            Token::Len => write!(f, "len"),
+            Token::Get => write!(f, "get"),
+            Token::Import => write!(f, "import"),
+            Token::Macro => write!(f, "%macro"),
+            Token::EndMacro => write!(f, "%endmacro"),
+            Token::Define => write!(f, ".define"),
+            Token::Equ => write!(f, ".equ"),
+            Token::Reg => write!(f, ".reg"),
+            Token::Data => write!(f, ".data"),
+            Token::Extern => write!(f, ".extern"),
             Token::String => write!(f, "string"),
             Token::Number => write!(f, "number"),
+            Token::Char => write!(f, "char"),
+            Token::True => write!(f, "true"),
+            Token::False => write!(f, "false"),
+            Token::Null => write!(f, "null"),
             Token::Label => write!(f, "label"),
             Token::Function => write!(f, "function"),
             Token::Register => write!(f, "register"),
+            Token::Identifier => write!(f, "identifier"),
             Token::Operand => write!(f, "operand"),
             Token::Instruction => write!(f, "instruction"),
+            Token::Invalid => write!(f, "invalid token"),
             Token::EOL => write!(f, "end of line"),
             Token::EOF => write!(f, "end of file"),
         }
@@ -566,4 +738,99 @@ mod tests {
             Token::EOL,
         ]);
     }
+
+    #[test]
+    fn lex_spans() {
+        let source = "@entrypoint\n  MOVE  %10, \"ação\"\n  RET";
+        let mut lexer = Lexer::new(source);
+
+        let mut spans = vec![];
+
+        loop {
+            let token = lexer.next().unwrap().unwrap();
+
+            if token == Token::EOF {
+                break;
+            }
+
+            spans.push(lexer.span());
+        }
+
+        assert_eq!(spans[0], Span { line: 1, column: 1, start: 0, end: 11 });
+        assert_eq!(spans[2], Span { line: 2, column: 3, start: 14, end: 18 });
+        assert_eq!(spans[3], Span { line: 2, column: 9, start: 20, end: 23 });
+        assert_eq!(spans[5], Span { line: 2, column: 14, start: 25, end: 33 });
+        assert_eq!(spans[7], Span { line: 3, column: 3, start: 36, end: 39 });
+    }
+
+    #[test]
+    fn lex_invalid_character() {
+        let mut lexer = Lexer::new("MOVE $ %0");
+
+        let _ = lexer.next();
+        let invalid = lexer.next().unwrap();
+
+        assert_eq!(invalid, Err(MachinaError::InvalidCharacter('$')));
+        assert_eq!(lexer.span(), Span { line: 1, column: 6, start: 5, end: 6 });
+
+        let (reg, _) = next_token(&mut lexer);
+
+        assert_eq!(reg, Token::Register);
+    }
+
+    #[test]
+    fn lex_macro() {
+        let mut lexer = Lexer::new("%macro twice(reg)\n  twice %0\n%endmacro");
+
+        assert_eq!(next_token(&mut lexer), (Token::Macro, None));
+        assert_eq!(next_token(&mut lexer), (Token::Identifier, Some("twice".into())));
+        assert_eq!(next_token(&mut lexer), (Token::LParen, None));
+        assert_eq!(next_token(&mut lexer), (Token::Identifier, Some("reg".into())));
+        assert_eq!(next_token(&mut lexer), (Token::RParen, None));
+        assert_eq!(next_token(&mut lexer), (Token::EOL, None));
+        assert_eq!(next_token(&mut lexer), (Token::Identifier, Some("twice".into())));
+        assert_eq!(next_token(&mut lexer), (Token::Register, Some("0".into())));
+        assert_eq!(next_token(&mut lexer), (Token::EOL, None));
+        assert_eq!(next_token(&mut lexer), (Token::EndMacro, None));
+    }
+
+    #[test]
+    fn lex_define() {
+        let mut lexer = Lexer::new(".define N = (SIZE - 1) * 2 +1");
+
+        assert_eq!(next_token(&mut lexer), (Token::Define, None));
+        assert_eq!(next_token(&mut lexer), (Token::Identifier, Some("N".into())));
+        assert_eq!(next_token(&mut lexer), (Token::Equal, None));
+        assert_eq!(next_token(&mut lexer), (Token::LParen, None));
+        assert_eq!(next_token(&mut lexer), (Token::Identifier, Some("SIZE".into())));
+        assert_eq!(next_token(&mut lexer), (Token::Minus, None));
+        assert_eq!(next_token(&mut lexer), (Token::Number, Some("1".into())));
+        assert_eq!(next_token(&mut lexer), (Token::RParen, None));
+        assert_eq!(next_token(&mut lexer), (Token::Star, None));
+        assert_eq!(next_token(&mut lexer), (Token::Number, Some("2".into())));
+        assert_eq!(next_token(&mut lexer), (Token::Number, Some("+1".into())));
+    }
+
+    #[test]
+    fn lex_typed_literals() {
+        let mut lexer = Lexer::new(r"MOVE %0, 'a', '\n', true, FALSE, null");
+
+        assert_eq!(next_token(&mut lexer), (Token::Move, None));
+        assert_eq!(next_token(&mut lexer), (Token::Register, Some("0".into())));
+        assert_eq!(next_token(&mut lexer), (Token::Comma, None));
+        assert_eq!(next_token(&mut lexer), (Token::Char, Some("a".into())));
+        assert_eq!(next_token(&mut lexer), (Token::Comma, None));
+        assert_eq!(next_token(&mut lexer), (Token::Char, Some("\n".into())));
+        assert_eq!(next_token(&mut lexer), (Token::Comma, None));
+        assert_eq!(next_token(&mut lexer), (Token::True, None));
+        assert_eq!(next_token(&mut lexer), (Token::Comma, None));
+        assert_eq!(next_token(&mut lexer), (Token::False, None));
+        assert_eq!(next_token(&mut lexer), (Token::Comma, None));
+        assert_eq!(next_token(&mut lexer), (Token::Null, None));
+
+        let mut lexer = Lexer::new("'ab' ''");
+
+        assert_eq!(lexer.next().unwrap(), Err(MachinaError::InvalidChar("ab".into())));
+        assert_eq!(lexer.next().unwrap(), Err(MachinaError::InvalidChar("".into())));
+    }
 }
@@ -5,10 +5,24 @@
 pub mod macros;
 
 pub mod machina;
+pub mod actor;
 pub mod value;
 pub mod object;
 pub mod error;
+pub mod io;
 pub mod parser;
+pub mod linker;
+pub mod objfile;
+pub mod optimizer;
 pub mod lexer;
 pub mod bytecode;
+pub mod encoding;
+pub mod feedback;
+pub mod aot;
+
+#[cfg(all(feature = "jit", not(all(target_arch = "x86_64", target_os = "linux"))))]
+compile_error!("the `jit` feature is only supported on x86-64 Linux");
+
+#[cfg(feature = "jit")]
+pub mod jit;
 
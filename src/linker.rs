@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+use crate::{
+    bytecode::{
+        Module,
+        OpCode,
+        Operand,
+    },
+    error::{
+        Diagnostics,
+        MachinaError,
+    },
+};
+
+#[derive(Debug, Default)]
+pub struct Linker {
+    modules: Vec<Module>,
+}
+
+impl Linker {
+
+    pub fn new() -> Linker {
+        Linker::default()
+    }
+
+    pub fn add(&mut self, module: Module) {
+        self.modules.push(module);
+    }
+
+    // Lays out the functions and constants of every module one after the
+    // other, in the order they were added, and rewrites the operands that
+    // point into them. The first module names the linked one
+    pub fn link(self) -> std::result::Result<Module, Diagnostics> {
+        let mut diagnostics = Diagnostics::new();
+
+        let file = self.modules.first().map(|module| module.file.clone()).unwrap_or_default();
+
+        let mut symbols = HashMap::new();
+        let mut offset = 0;
+
+        for module in self.modules.iter() {
+            for (index, function) in module.functions.iter().enumerate() {
+                if symbols.contains_key(&function.name) {
+                    let error = MachinaError::DuplicateFunction(function.name.clone(), module.file.clone());
+                    let _ = diagnostics.report::<()>(error);
+                } else {
+                    symbols.insert(function.name.clone(), offset + index);
+                }
+            }
+
+            offset += module.functions.len();
+        }
+
+        // Operands only have room for this many functions
+        if offset > u16::MAX as usize + 1 {
+            let _ = diagnostics.report::<()>(MachinaError::TooManyFunctions(offset));
+            return Err(diagnostics);
+        }
+
+        let mut functions = vec![];
+        let mut constants = vec![];
+
+        // Equal constants of different modules share one entry of the pool
+        let mut interned = HashMap::new();
+
+        for module in self.modules {
+            let externs = module.externs
+                .iter()
+                .map(|name| {
+                    let function = symbols.get(name).copied();
+
+                    if function.is_none() {
+                        let error = MachinaError::UnresolvedFunction(name.clone(), module.file.clone());
+                        let _ = diagnostics.report::<()>(error);
+                    }
+
+                    function.unwrap_or_default()
+                })
+                .collect::<Vec<_>>();
+
+            let locals = module.functions.len();
+            let function_offset = functions.len();
+
+            let pool = module.constants
+                .into_iter()
+                .map(|constant| {
+                    *interned.entry(constant).or_insert_with_key(|constant| {
+                        constants.push(constant.clone());
+                        constants.len() - 1
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for mut function in module.functions {
+                function.edit(|instructions| {
+                    for instruction in instructions.iter_mut() {
+                        for operand in instruction.operands.iter_mut() {
+                            match operand {
+                                Operand::Function(index) if (*index as usize) < locals => {
+                                    *index += function_offset as u16;
+                                }
+                                Operand::Function(index) => {
+                                    *index = externs[*index as usize - locals] as u16;
+                                }
+                                Operand::Constant(index) => {
+                                    *operand = Operand::constant(pool[*index as usize]);
+                                }
+                                Operand::WideConstant(index) => {
+                                    *operand = Operand::constant(pool[*index as usize]);
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                });
+
+                functions.push(function);
+            }
+        }
+
+        if !diagnostics.empty() {
+            return Err(diagnostics);
+        }
+
+        // Calls inside a module were checked when it was assembled, but not
+        // the ones into another module
+        for function in functions.iter() {
+            for instruction in function.instructions().iter() {
+                if let (OpCode::Call | OpCode::Spawn, Operand::Function(index), Operand::Register(first), Operand::Register(last))
+                    = (instruction.opcode, instruction.get(0), instruction.get(2), instruction.get(3))
+                {
+                    let callee = &functions[index as usize];
+                    let count = (last as usize + 1).saturating_sub(first as usize);
+
+                    if !callee.accepts(count) {
+                        let error = MachinaError::ArityMismatch(callee.name.clone(), callee.arity.unwrap_or_default(), count);
+                        let _ = diagnostics.report::<()>(error);
+                    }
+                }
+            }
+        }
+
+        if !diagnostics.empty() {
+            return Err(diagnostics);
+        }
+
+        Ok(Module {
+            file,
+            functions,
+            constants,
+            symbols,
+            imports: vec![],
+            externs: vec![],
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        bytecode::{
+            Constant,
+            Function,
+        },
+        parser::Parser,
+    };
+
+    fn link(sources: &[(&str, &str)]) -> std::result::Result<Module, Diagnostics> {
+        let mut linker = Linker::new();
+
+        for (file, source) in sources {
+            linker.add(Parser::new(source).with_file(file).parse().unwrap());
+        }
+
+        linker.link()
+    }
+
+    #[test]
+    fn relocate_functions_and_constants() {
+        let module = link(&[
+            ("main.machina", r#"
+                import "greet.machina"
+                .extern @greet
+
+                @entrypoint
+                  MOVE      %0, "main"
+                  CALL      @greet, %0, %0, %0
+                  RET       %0
+            "#),
+            ("greet.machina", r#"
+                @greet
+                  MOVE      %1, "hello"
+                  CALL      @helper, %1, %1, %1
+                  RET       %0
+
+                @helper
+                  RET       %0
+            "#),
+        ]).unwrap();
+
+        assert_eq!(module.function("entrypoint"), Some(0));
+        assert_eq!(module.function("greet"), Some(1));
+        assert_eq!(module.function("helper"), Some(2));
+
+        assert_eq!(module.functions[0].instructions()[1].get(0), Operand::Function(1));
+        assert_eq!(module.functions[1].instructions()[0].get(1), Operand::Constant(1));
+        assert_eq!(module.functions[1].instructions()[1].get(0), Operand::Function(2));
+        assert_eq!(module.constants[1], Constant::String("hello".into()));
+        assert_eq!(module.functions[1].source_map.file, "greet.machina");
+    }
+
+    #[test]
+    fn intern_constants_across_modules() {
+        let module = link(&[
+            ("main.machina", r#"
+                import "lib.machina"
+
+                @entrypoint
+                  MOVE      %0, "shared"
+                  MOVE      %1, 'm'
+                  RET       %0
+            "#),
+            ("lib.machina", r#"
+                @helper
+                  MOVE      %0, 'l'
+                  MOVE      %1, "shared"
+                  RET       %0
+            "#),
+        ]).unwrap();
+
+        assert_eq!(module.constants, vec![
+            Constant::String("shared".into()),
+            Constant::Char('m'),
+            Constant::Char('l'),
+        ]);
+        assert_eq!(module.functions[1].instructions()[0].get(1), Operand::Constant(2));
+        assert_eq!(module.functions[1].instructions()[1].get(1), Operand::Constant(0));
+    }
+
+    #[test]
+    fn duplicate_and_unresolved_functions() {
+        let diagnostics = link(&[
+            ("main.machina", r#"
+                import "other.machina"
+                .extern @missing
+
+                @entrypoint
+                  CALL      @missing, %0, %0, %0
+                  RET       %0
+            "#),
+            ("other.machina", r#"
+                @entrypoint
+                  RET
+            "#),
+        ]).unwrap_err();
+
+        assert_eq!(diagnostics.errors().cloned().collect::<Vec<_>>(), vec![
+            MachinaError::DuplicateFunction("entrypoint".into(), "other.machina".into()),
+            MachinaError::UnresolvedFunction("missing".into(), "main.machina".into()),
+        ]);
+    }
+
+    #[test]
+    fn check_arity_across_modules() {
+        let diagnostics = link(&[
+            ("main.machina", r#"
+                import "lib.machina"
+                .extern @pair
+
+                @entrypoint
+                  CALL      @pair, %0, %0, %0
+                  RET       %0
+            "#),
+            ("lib.machina", r#"
+                @pair(2)
+                  RET       %0
+            "#),
+        ]).unwrap_err();
+
+        assert_eq!(diagnostics.errors().cloned().collect::<Vec<_>>(), vec![
+            MachinaError::ArityMismatch("pair".into(), 2, 1),
+        ]);
+    }
+
+    #[test]
+    fn too_many_functions() {
+        let mut linker = Linker::new();
+
+        for file in ["a.machina", "b.machina"] {
+            let functions = (0 .. 40_000)
+                .map(|index| Function::new(format!("{}{}", file, index), 0, vec![]))
+                .collect();
+
+            linker.add(Module {
+                file: file.into(),
+                functions,
+                constants: vec![],
+                symbols: HashMap::new(),
+                imports: vec![],
+                externs: vec![],
+            });
+        }
+
+        let diagnostics = linker.link().unwrap_err();
+
+        assert_eq!(diagnostics.errors().cloned().collect::<Vec<_>>(), vec![
+            MachinaError::TooManyFunctions(80_000),
+        ]);
+    }
+}
@@ -1,15 +1,52 @@
 use crate::{
+    actor::{
+        Actor,
+        Message,
+    },
     bytecode::{
         Constant,
         Function,
+        Instruction,
         OpCode,
         Operand,
         Register,
     },
+    feedback,
+    io::{
+        Input,
+        Output,
+    },
+    error::{
+        Frame,
+        MachinaError,
+        RuntimeResult,
+    },
+    object::Object,
     value::Value,
 };
 
-use std::fmt::Debug;
+#[cfg(feature = "jit")]
+use crate::jit::{
+    self,
+    Context,
+    Jit,
+};
+
+#[cfg(any(not(feature = "threaded"), feature = "jit"))]
+use crate::encoding::{
+    self,
+    Op,
+};
+
+use std::fmt::{
+    self,
+    Debug,
+};
+
+// The jit takes over hot loops from their back edge in the `match` loop, so
+// it wins when both features are enabled
+#[cfg(all(feature = "threaded", not(feature = "jit")))]
+mod threaded;
 
 const INITIAL_REG_SIZE: usize = 16;
 
@@ -18,14 +55,21 @@ const INITIAL_REG_SIZE: usize = 16;
 pub struct Environment {
     pub functions: Vec<Function>,
     pub constants: Vec<Constant>,
+
+    // The handlers of each function, decoded once for every machine
+    // running it, see `threaded.rs`
+    #[cfg(all(feature = "threaded", not(feature = "jit")))]
+    threaded: Vec<Box<[threaded::Threaded]>>,
 }
 
 impl Environment {
 
-    pub fn new() -> Environment {
+    pub fn new(functions: Vec<Function>, constants: Vec<Constant>) -> Environment {
         Environment {
-            constants: vec![],
-            functions: vec![],
+            #[cfg(all(feature = "threaded", not(feature = "jit")))]
+            threaded: functions.iter().map(|function| threaded::decode(function.code())).collect(),
+            functions,
+            constants,
         }
     }
 
@@ -34,29 +78,115 @@ impl Environment {
     }
 }
 
+fn opcode_name(opcode: OpCode) -> String {
+    format!("{:?}", opcode).to_lowercase()
+}
+
 #[derive(Debug)]
 pub struct Machina<'a> {
     registers: Vec<Value>,
     bp: usize,
     rp: usize,
-    environment: &'a Environment
+    #[allow(clippy::vec_box)]
+    heap: Vec<Box<Object>>, // boxed so values can keep pointing at them
+    constants: Vec<Value>,
+    actor: Option<Actor<'a>>,
+    halted: bool,
+    input: Input,
+    output: Output,
+    environment: &'a Environment,
+    #[cfg(feature = "jit")]
+    jit: Jit,
 }
 
 impl<'a> Machina<'a> {
     pub fn new(env: &'a Environment) -> Machina<'a> {
-        Machina {
+        let mut machina = Machina {
             registers: vec![Value::null(); INITIAL_REG_SIZE],
             bp: 0,
             rp: 0,
+            heap: vec![],
+            constants: vec![],
+            actor: None,
+            halted: false,
+            input: Input::default(),
+            output: Output::default(),
             environment: env,
+            #[cfg(feature = "jit")]
+            jit: Jit::new(Some(jit::THRESHOLD)),
+        };
+
+        machina.constants = env.constants
+            .iter()
+            .map(|constant| machina.materialize(constant))
+            .collect();
+
+        machina
+    }
+
+    fn materialize(&mut self, constant: &Constant) -> Value {
+        match constant {
+            Constant::String(string) => self.allocate(Object::String(string.clone())),
+            Constant::Number(num) => Value::from(num.value()),
+            Constant::Integer(int) => Value::from(*int),
+            Constant::Char(chr) => Value::from(*chr),
+            Constant::Boolean(boolean) => Value::from(*boolean),
+            Constant::Null => Value::null(),
+            Constant::List(constants) => {
+                let values = constants.iter().map(|constant| self.materialize(constant)).collect();
+                self.allocate(Object::List(values))
+            }
+            Constant::Bytes(bytes) => self.allocate(Object::Bytes(bytes.clone())),
+        }
+    }
+
+    pub fn with_input(mut self, input: Input) -> Machina<'a> {
+        self.input = input;
+        self
+    }
+
+    pub fn with_output(mut self, output: Output) -> Machina<'a> {
+        self.output = output;
+        self
+    }
+
+    // Compiles functions once called or looped `threshold` times, or never
+    #[cfg(feature = "jit")]
+    pub fn with_jit(mut self, threshold: Option<u32>) -> Machina<'a> {
+        self.jit = Jit::new(threshold);
+        self
+    }
+
+    pub fn attach(mut self, actor: Actor<'a>) -> Machina<'a> {
+        self.actor = Some(actor);
+        self
+    }
+
+    pub fn actor(&self) -> Option<&Actor<'a>> {
+        self.actor.as_ref()
+    }
+
+    pub fn start(&mut self, index: usize, args: Vec<Message>) -> RuntimeResult<Value> {
+        let count = args.len();
+
+        self.resize_registers(count);
+
+        for (idx, arg) in args.into_iter().enumerate() {
+            self.registers[idx] = self.import(arg);
         }
+
+        let last = if count == 0 { 0 } else { count - 1 };
+
+        self.call(index, 0, last as Register)
     }
 
-    pub fn call(&mut self, index: usize, first: Register, last: Register) -> Value {
+    pub fn call(&mut self, index: usize, first: Register, last: Register) -> RuntimeResult<Value> {
 
         let function = self.environment.get_function(index);
 
-        self.resize_registers(((last - first) + 1) as usize);
+        let count = ((last - first) + 1) as usize;
+
+        self.resize_registers(count.max(function.locals as usize));
 
         for (idx, reg) in (first ..= last).enumerate() {
             let new = self.rp + idx as usize;
@@ -68,7 +198,7 @@ impl<'a> Machina<'a> {
         let _rp = self.rp;
         self.bp = self.rp;
 
-        let value = self.eval(function);
+        let value = self.eval(index, function);
 
         self.rp = _rp;
         self.bp = _bp;
@@ -76,81 +206,303 @@ impl<'a> Machina<'a> {
         value
     }
 
-    fn eval(&mut self, function: &Function) -> Value {
+    fn eval(&mut self, index: usize, function: &Function) -> RuntimeResult<Value> {
         self.alloc(function.locals as usize);
 
         let mut ip  = 0;
 
+        self.run(index, function, &mut ip).map_err(|mut error| {
+            error.trace.push(Frame::new(function, ip - 1));
+            error
+        })
+    }
+
+    // Moves between the interpreter and the native code of the function,
+    // for as long as it keeps deopting and getting hot again
+    fn run(&mut self, index: usize, function: &Function, ip: &mut usize) -> RuntimeResult<Value> {
+        #[cfg(feature = "jit")]
+        let mut entry = self.jit.call(index, function, native_step);
+
+        loop {
+            #[cfg(feature = "jit")]
+            {
+                if let Some(entry) = entry {
+                    if let Some(value) = self.native(index, entry, function, ip)? {
+                        return Ok(value);
+                    }
+                }
+            }
+
+            if let Some(value) = self.execute(index, function, ip)? {
+                return Ok(value);
+            }
+
+            #[cfg(feature = "jit")]
+            {
+                entry = self.jit.entry(index, function, native_step);
+            }
+        }
+    }
+
+    // Runs the function natively from `ip`, leaving `ip` wherever it deopts
+    #[cfg(feature = "jit")]
+    fn native(&mut self, index: usize, entry: jit::Entry, function: &Function, ip: &mut usize) -> RuntimeResult<Option<Value>> {
+        let mut context = Context::new(self.frame(), self.constants.as_ptr(), self as *mut Machina as *mut (), function);
+
+        match unsafe { entry(&mut context, *ip) } {
+            jit::RETURN => Ok(Some(context.value)),
+            jit::ERROR => {
+                *ip = context.ip;
+                Err(context.error.take().unwrap())
+            }
+            deopt => {
+                *ip = deopt as usize;
+                self.jit.deopt(index);
+                Ok(None)
+            }
+        }
+    }
+
+    #[cfg(feature = "jit")]
+    fn frame(&mut self) -> *mut Value {
+        unsafe { self.registers.as_mut_ptr().add(self.bp) }
+    }
+
+    #[cfg(all(feature = "threaded", not(feature = "jit")))]
+    #[inline(always)]
+    fn execute(&mut self, index: usize, function: &Function, ip: &mut usize) -> RuntimeResult<Option<Value>> {
+        threaded::execute(self, index, function, ip)
+    }
+
+    // Returns nothing when a loop got hot enough to continue natively
+    #[cfg(any(not(feature = "threaded"), feature = "jit"))]
+    #[inline(always)]
+    #[cfg_attr(not(feature = "jit"), allow(unused_variables))]
+    fn execute(&mut self, index: usize, function: &Function, ip: &mut usize) -> RuntimeResult<Option<Value>> {
         loop {
-            let instruction = function.instructions[ip];
-            ip += 1;
+            let word = function.code()[*ip];
+            *ip += 1;
 
-            match instruction.opcode {
-                OpCode::Move => {
-                    self.set(instruction.register(0), self.get(instruction.get(1)));
+            let a = encoding::a(word) as usize;
+            let x = encoding::x(word);
+
+            packed_ops!(dispatch!(self, function, index, word, ip, a, x));
+        }
+    }
+
+    // Runs an instruction from its unpacked form, returning a value once the
+    // function returns
+    fn step(&mut self, function: &Function, ip: &mut usize) -> RuntimeResult<Option<Value>> {
+        let instruction = function.instructions()[*ip - 1];
+
+        match instruction.opcode {
+            OpCode::Move => {
+                self.set(instruction.register(0), self.get(instruction.get(1)));
+            }
+            OpCode::Call => {
+                return self.invoke(instruction);
+            }
+            OpCode::Spawn => {
+                let first = instruction.register(2);
+                let last  = instruction.register(3);
+
+                if first > last {
+                    return Err(MachinaError::InvalidRange(opcode_name(instruction.opcode)).into());
                 }
-                OpCode::Call => {
-                    let first = instruction.register(2);
-                    let last  = instruction.register(3);
 
-                    if first > last {
-                        panic!("Invalid register range for CALL instruction")
-                    }
+                let args = (first ..= last)
+                    .map(|reg| self.export(self.get(Operand::Register(reg))))
+                    .collect();
 
-                    let val = self.call(instruction.function(0) as usize, first, last);
+                let child = Machina::new(self.environment)
+                    .with_input(self.input.clone())
+                    .with_output(self.output.clone());
 
-                    self.set(instruction.register(1), val);
+                #[cfg(feature = "jit")]
+                let child = child.with_jit(self.jit.threshold());
+
+                let id = self.current_actor(instruction.opcode)?
+                    .spawn(child, instruction.function(0) as usize, args);
+
+                self.set(instruction.register(1), Value::from(id));
+            }
+            OpCode::Send => {
+                let to = self.get(instruction.get(0));
+                let message = self.export(self.get(instruction.get(1)));
+
+                if !to.is_int() {
+                    return Err(MachinaError::ActorNotFound(to.to_string()).into());
                 }
-                OpCode::Jmp => {
-                    ip = instruction.position(0) as usize;
+
+                let sent = self.current_actor(instruction.opcode)?
+                    .send(to.get_int(), message);
+
+                if !sent {
+                    return Err(MachinaError::ActorNotFound(to.to_string()).into());
                 }
-                OpCode::Jt => {
-                    let val = self.get(instruction.get(1));
-                    if val.is_true() {
-                        ip = instruction.position(0) as usize;
+            }
+            OpCode::Recv => {
+                let message = self.current_actor(instruction.opcode)?
+                    .receive();
+
+                match message {
+                    Some(message) => {
+                        let val = self.import(message);
+                        self.set(instruction.register(0), val);
+                    }
+                    None => {
+                        self.halted = true;
+                        return Ok(Some(Value::null()));
                     }
                 }
-                OpCode::Jf => {
-                    let val = self.get(instruction.get(1));
-                    if val.is_false() {
-                        ip = instruction.position(0) as usize;
+            }
+            OpCode::Read
+          | OpCode::ReadLn
+          | OpCode::ReadInt
+          | OpCode::ReadNum => {
+                let val = match self.input.read_line() {
+                    Some(line) => self.read(instruction.opcode, line),
+                    None => Value::null(),
+                };
+
+                self.set(instruction.register(0), val);
+            }
+            OpCode::Pid => {
+                let id = self.current_actor(instruction.opcode)?
+                    .id();
+
+                self.set(instruction.register(0), Value::from(id));
+            }
+            OpCode::Len => {
+                let value = self.get(instruction.get(1));
+
+                let len = self.length(value)
+                    .ok_or_else(|| MachinaError::InvalidOperand(opcode_name(instruction.opcode), self.type_name(value)))?;
+
+                self.set(instruction.register(0), Value::from(len as i64));
+            }
+            OpCode::Get => {
+                let value = self.get(instruction.get(1));
+                let index = self.get(instruction.get(2));
+
+                let len = match self.length(value) {
+                    Some(len) if index.is_int() => len,
+                    _ => {
+                        let error = MachinaError::InvalidOperands(opcode_name(instruction.opcode), self.type_name(value), self.type_name(index));
+                        return Err(error.into());
                     }
+                };
+
+                let index = index.get_int();
+
+                if index < 0 || index as usize >= len {
+                    return Err(MachinaError::IndexOutOfBounds(index as i64, len).into());
                 }
-                OpCode::JLt => jump_op!(self, instruction, ip, <),
-                OpCode::JLe => jump_op!(self, instruction, ip, <=),
-                OpCode::JGt => jump_op!(self, instruction, ip, >),
-                OpCode::JGe => jump_op!(self, instruction, ip, >=),
-                OpCode::JEq => jump_op!(self, instruction, ip, ==),
-                OpCode::JNe => jump_op!(self, instruction, ip, !=),
-                OpCode::Lt  => binary_op!(self, instruction, <),
-                OpCode::Le  => binary_op!(self, instruction, <=),
-                OpCode::Gt  => binary_op!(self, instruction, >),
-                OpCode::Ge  => binary_op!(self, instruction, >=),
-                OpCode::Eq  => binary_op!(self, instruction, ==),
-                OpCode::Ne  => binary_op!(self, instruction, !=),
-                OpCode::Add => binary_op!(self, instruction, +),
-                OpCode::Sub => binary_op!(self, instruction, -),
-                OpCode::Mul => binary_op!(self, instruction, *),
-                OpCode::Div => binary_op!(self, instruction, /),
-                OpCode::Mod => integer_op!(self, instruction, %),
-                OpCode::And => integer_op!(self, instruction, &),
-                OpCode::Or  => integer_op!(self, instruction, |),
-                OpCode::Xor => integer_op!(self, instruction, ^),
-                OpCode::Shl => integer_op!(self, instruction, <<),
-                OpCode::Shr => integer_op!(self, instruction, >>),
-                OpCode::Not => unary_op!(self, instruction, !),
-                OpCode::Ret => {
-                    return self.get(instruction.get(0));
+
+                let element = match self.object(value) {
+                    Object::List(values) => values[index as usize],
+                    Object::Bytes(bytes) => Value::from(bytes[index as usize] as i32),
+                    Object::String(string) => Value::from(string.chars().nth(index as usize).unwrap()),
+                    _ => unreachable!(),
+                };
+
+                self.set(instruction.register(0), element);
+            }
+            OpCode::Jmp => {
+                *ip = instruction.position(0) as usize;
+            }
+            OpCode::Jt => {
+                let val = self.get(instruction.get(1));
+                if val.is_true() {
+                    *ip = instruction.position(0) as usize;
                 }
-                OpCode::Write => {
-                    if instruction.get(0) == Operand::None {
-                        println!("\n");
-                    } else {
-                        println!("{}", self.get(instruction.get(0)));
-                    }
+            }
+            OpCode::Jf => {
+                let val = self.get(instruction.get(1));
+                if val.is_false() {
+                    *ip = instruction.position(0) as usize;
                 }
             }
+            OpCode::JLt => jump_op!(self, instruction, *ip, <),
+            OpCode::JLe => jump_op!(self, instruction, *ip, <=),
+            OpCode::JGt => jump_op!(self, instruction, *ip, >),
+            OpCode::JGe => jump_op!(self, instruction, *ip, >=),
+            OpCode::JEq => jump_op!(self, instruction, *ip, ==),
+            OpCode::JNe => jump_op!(self, instruction, *ip, !=),
+            OpCode::Lt  => binary_op!(self, function, *ip - 1, instruction, <),
+            OpCode::Le  => binary_op!(self, function, *ip - 1, instruction, <=),
+            OpCode::Gt  => binary_op!(self, function, *ip - 1, instruction, >),
+            OpCode::Ge  => binary_op!(self, function, *ip - 1, instruction, >=),
+            OpCode::Eq  => binary_op!(self, function, *ip - 1, instruction, ==),
+            OpCode::Ne  => binary_op!(self, function, *ip - 1, instruction, !=),
+            OpCode::Add => binary_op!(self, function, *ip - 1, instruction, +),
+            OpCode::Sub => binary_op!(self, function, *ip - 1, instruction, -),
+            OpCode::Mul => binary_op!(self, function, *ip - 1, instruction, *),
+            OpCode::Div => {
+                self.check_divisor(instruction.sources(), false)?;
+                binary_op!(self, function, *ip - 1, instruction, /)
+            }
+            OpCode::Mod => {
+                self.check_divisor(instruction.sources(), true)?;
+                integer_op!(self, instruction, %)
+            }
+            OpCode::And => integer_op!(self, instruction, &),
+            OpCode::Or  => integer_op!(self, instruction, |),
+            OpCode::Xor => integer_op!(self, instruction, ^),
+            OpCode::Shl => integer_op!(self, instruction, <<),
+            OpCode::Shr => integer_op!(self, instruction, >>),
+            OpCode::Not => unary_op!(self, instruction, !),
+            OpCode::Ret => {
+                return Ok(Some(self.get(instruction.get(0))));
+            }
+            OpCode::Write => {
+                let written = if instruction.get(0) == Operand::None {
+                    self.output.write(format_args!("\n\n"))
+                } else {
+                    self.output.write(format_args!("{}\n", self.display(self.get(instruction.get(0)))))
+                };
+
+                written.map_err(|error| MachinaError::Io(error.to_string()))?;
+            }
+            OpCode::Print => {
+                self.output
+                    .write(format_args!("{}", self.display(self.get(instruction.get(0)))))
+                    .map_err(|error| MachinaError::Io(error.to_string()))?;
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Kept out of `step`, so the native code doesn't need its whole frame
+    // for every call
+    fn invoke(&mut self, instruction: Instruction) -> RuntimeResult<Option<Value>> {
+        let first = instruction.register(2);
+        let last  = instruction.register(3);
+
+        if first > last {
+            return Err(MachinaError::InvalidRange(opcode_name(instruction.opcode)).into());
         }
+
+        let val = self.call(instruction.function(0) as usize, first, last)?;
+
+        if self.halted {
+            return Ok(Some(Value::null()));
+        }
+
+        self.set(instruction.register(1), val);
+
+        Ok(None)
+    }
+
+    #[inline(always)]
+    fn load(&self, reg: usize) -> Value {
+        self.registers[self.bp + reg]
+    }
+
+    #[inline(always)]
+    fn store(&mut self, reg: usize, value: Value) {
+        self.registers[self.bp + reg] = value;
     }
 
     #[inline(always)]
@@ -168,27 +520,622 @@ impl<'a> Machina<'a> {
                 Value::from(imm)
             }
             Operand::Constant(idx) => {
-                match self.environment.constants[idx as usize] {
-                    Constant::String(_) => {
-                        todo!()
-                    }
-                    Constant::Number(num) => Value::from(num.value()),
-                }
+                self.constants[idx as usize]
+            }
+            Operand::WideConstant(idx) => {
+                self.constants[idx as usize]
             }
             _ => Value::null()
         }
     }
 
+    // Number of elements of a list, bytes or string
+    fn length(&self, value: Value) -> Option<usize> {
+        if !value.is_ptr() {
+            return None;
+        }
+
+        match self.object(value) {
+            Object::List(values) => Some(values.len()),
+            Object::Bytes(bytes) => Some(bytes.len()),
+            Object::String(string) => Some(string.chars().count()),
+            _ => None,
+        }
+    }
+
+    fn current_actor(&self, opcode: OpCode) -> Result<&Actor<'a>, MachinaError> {
+        self.actor
+            .as_ref()
+            .ok_or_else(|| MachinaError::NoActorSystem(opcode_name(opcode)))
+    }
+
+    fn check_divisor(&self, (lhs, rhs): (Operand, Operand), integer: bool) -> Result<(), MachinaError> {
+        let lhs = self.get(lhs);
+        let rhs = self.get(rhs);
+
+        let integer = integer || (lhs.is_int() && rhs.is_int());
+
+        if integer && rhs.is_numeric() && rhs.as_int() == 0 {
+            Err(MachinaError::DivisionByZero)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read(&mut self, opcode: OpCode, line: String) -> Value {
+        let text = line.trim();
+
+        match opcode {
+            OpCode::ReadInt => {
+                text.parse::<i64>().map(Value::from).unwrap_or(Value::null())
+            }
+            OpCode::ReadNum => {
+                text.parse::<f64>().map(Value::from).unwrap_or(Value::null())
+            }
+            OpCode::Read => {
+                if let Ok(int) = text.parse::<i64>() {
+                    Value::from(int)
+                } else if let Ok(num) = text.parse::<f64>() {
+                    Value::from(num)
+                } else {
+                    self.allocate(Object::String(line))
+                }
+            }
+            _ => {
+                self.allocate(Object::String(line))
+            }
+        }
+    }
+
+    pub fn export(&self, value: Value) -> Message {
+        if value.is_ptr() {
+            match self.object(value) {
+                Object::String(string) => Message::String(string.clone()),
+                Object::Number(num) => Message::Value(Value::from(num.value())),
+                Object::Integer(int) => Message::Value(Value::from(*int)),
+                Object::Boolean(boolean) => Message::Value(Value::from(*boolean)),
+                Object::List(values) => Message::List(values.iter().map(|value| self.export(*value)).collect()),
+                Object::Bytes(bytes) => Message::Bytes(bytes.clone()),
+                Object::Null => Message::Value(Value::null()),
+            }
+        } else {
+            Message::Value(value)
+        }
+    }
+
+    pub fn import(&mut self, message: Message) -> Value {
+        match message {
+            Message::Value(value) => value,
+            Message::String(string) => self.allocate(Object::String(string)),
+            Message::List(messages) => {
+                let values = messages.into_iter().map(|message| self.import(message)).collect();
+                self.allocate(Object::List(values))
+            }
+            Message::Bytes(bytes) => self.allocate(Object::Bytes(bytes)),
+        }
+    }
+
+    fn allocate(&mut self, object: Object) -> Value {
+        let object = Box::new(object);
+        let value = Value::ptr::<Object>(&*object);
+        self.heap.push(object);
+        value
+    }
+
+    #[inline(always)]
+    fn object(&self, value: Value) -> &Object {
+        unsafe { &*value.get_ptr::<Object>() }
+    }
+
+    fn type_name(&self, value: Value) -> String {
+        if value.is_ptr() {
+            self.object(value).type_name().into()
+        } else {
+            value.type_name()
+        }
+    }
+
+    // A value only holds the address of its object, so printing one has to
+    // go through the machine that owns it
+    pub fn display(&self, value: Value) -> Display<'_, 'a> {
+        Display { machina: self, value }
+    }
+
+    // The next frame starts right after the last register of this one
     fn alloc(&mut self, total: usize) {
-        self.rp = (self.bp + total as usize) - 1;
+        self.rp = self.bp + total;
     }
 
     fn resize_registers(&mut self, total: usize) {
         let curr = self.registers.len();
         let diff = (curr as isize) - (self.rp + total) as isize;
         if diff <= 0 {
-            let new_size = 1.5 * curr as f32;
-            self.registers.resize(new_size as usize, Value::null());
+            let new_size = (1.5 * curr as f32) as usize;
+            let new_size = new_size.max(self.rp + total + 1);
+            self.registers.resize(new_size, Value::null());
+        }
+    }
+}
+
+pub struct Display<'m, 'a> {
+    machina: &'m Machina<'a>,
+    value: Value,
+}
+
+impl fmt::Display for Display<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.value.is_ptr() {
+            return write!(f, "{}", self.value);
+        }
+
+        match self.machina.object(self.value) {
+            Object::List(values) => {
+                write!(f, "[")?;
+                for (idx, value) in values.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", self.machina.display(*value))?;
+                }
+                write!(f, "]")
+            }
+            object => write!(f, "{}", object),
+        }
+    }
+}
+
+
+// The way back into the interpreter for the instructions without a native
+// form, see `jit::Step`
+#[cfg(feature = "jit")]
+extern "sysv64" fn native_step(context: *mut Context, ip: usize) -> i64 {
+    let context = unsafe { &mut *context };
+    let machina = unsafe { &mut *(context.machina as *mut Machina) };
+    let function = unsafe { &*context.function };
+
+    let mut next = ip + 1;
+
+    let instruction = function.instructions()[ip];
+
+    let result = match instruction.opcode {
+        OpCode::Call => machina.invoke(instruction),
+        _ => machina.step(function, &mut next),
+    };
+
+    context.registers = machina.frame();
+
+    match result {
+        Ok(None) => next as i64,
+        Ok(Some(value)) => {
+            context.value = value;
+            jit::RETURN
+        }
+        Err(error) => {
+            context.error = Some(error);
+            context.ip = next;
+            jit::ERROR
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use crate::{
+        bytecode::{Instruction, Module},
+        parser::Parser,
+    };
+
+    fn environment(source: &str) -> Environment {
+        let Module { functions, constants, .. } = Parser::new(source).parse().unwrap();
+        Environment::new(functions, constants)
+    }
+
+    #[test]
+    fn read_values() {
+        let environment = environment(r#"
+            @entrypoint
+              READINT   %0
+              READNUM   %1
+              ADD       %0, %1
+              RET       %0
+        "#);
+
+        let input = Input::new(Cursor::new("40\n2.5\n"));
+
+        let value = Machina::new(&environment).with_input(input).call(0, 0, 0).unwrap();
+
+        assert_eq!(value, Value::from(42.5));
+    }
+
+    #[test]
+    fn read_line() {
+        let environment = environment(r#"
+            @entrypoint
+              READLN    %0
+              RET       %0
+        "#);
+
+        let input = Input::new(Cursor::new("Hello, World\n"));
+
+        let mut machina = Machina::new(&environment).with_input(input);
+        let value = machina.call(0, 0, 0).unwrap();
+
+        assert_eq!(machina.export(value), Message::String("Hello, World".into()));
+    }
+
+    #[test]
+    fn read_guesses_the_type() {
+        let environment = environment(r#"
+            @entrypoint
+              READ      %0
+              READ      %1
+              READ      %2
+              READ      %3
+              RET       %0
+        "#);
+
+        let input = Input::new(Cursor::new("12\n1.5\nabc\n"));
+
+        let mut machina = Machina::new(&environment).with_input(input);
+        machina.call(0, 0, 0).unwrap();
+
+        assert_eq!(machina.registers[0], Value::from(12));
+        assert_eq!(machina.registers[1], Value::from(1.5));
+        assert_eq!(machina.export(machina.registers[2]), Message::String("abc".into()));
+        assert!(machina.registers[3].is_null());
+    }
+
+    #[test]
+    fn write_and_print() {
+        let environment = environment(r#"
+            @entrypoint
+              PRINT     "a"
+              PRINT     1
+              WRITE     2.5
+              RET
+        "#);
+
+        let output = Output::buffer();
+
+        Machina::new(&environment).with_output(output.clone()).call(0, 0, 0).unwrap();
+
+        assert_eq!(output.contents(), Some("a12.5\n".into()));
+    }
+
+    #[test]
+    fn typed_constants() {
+        let environment = environment(r#"
+            @entrypoint
+              PRINT     'a'
+              PRINT     true
+              PRINT     null
+              WRITE     3000000000
+              RET
+        "#);
+
+        let output = Output::buffer();
+
+        Machina::new(&environment).with_output(output.clone()).call(0, 0, 0).unwrap();
+
+        assert_eq!(output.contents(), Some("atruenull3000000000\n".into()));
+    }
+
+    #[test]
+    fn wide_constants() {
+        let mut constants = vec![Constant::Integer(0); u16::MAX as usize + 1];
+        constants.push(Constant::String("wide".into()));
+
+        let instructions = vec![
+            Instruction::new(OpCode::Move, [Operand::Register(0), Operand::WideConstant(u16::MAX as u32 + 1), Operand::None, Operand::None]),
+            Instruction::new(OpCode::Write, [Operand::Register(0), Operand::None, Operand::None, Operand::None]),
+            Instruction::new(OpCode::Ret, [Operand::None; 4]),
+        ];
+
+        let environment = Environment::new(vec![Function::new("entrypoint".into(), 1, instructions)], constants);
+
+        let output = Output::buffer();
+
+        Machina::new(&environment).with_output(output.clone()).call(0, 0, 0).unwrap();
+
+        assert_eq!(output.contents(), Some("wide\n".into()));
+    }
+
+    #[test]
+    fn three_address() {
+        let environment = environment(r#"
+            @entrypoint
+              MOVE      %0, 7
+              MOVE      %1, 2.5
+              ADD       %2, %0, 3
+              SUB       %3, 10, %0
+              MUL       %4, %0, %1
+              MOD       %5, %0, 4
+              LT        %6, %1, %0
+              WRITE     %2
+              WRITE     %3
+              WRITE     %4
+              WRITE     %5
+              WRITE     %6
+              WRITE     %0
+              DIV       %7, %0, 0
+              RET
+        "#);
+
+        let output = Output::buffer();
+
+        let error = Machina::new(&environment).with_output(output.clone()).call(0, 0, 0).unwrap_err();
+
+        assert_eq!(output.contents(), Some("10\n3\n17.5\n3\ntrue\n7\n".into()));
+        assert_eq!(error.error, MachinaError::DivisionByZero);
+    }
+
+    #[test]
+    fn superinstructions_fall_back() {
+        let environment = environment(r#"
+            @entrypoint
+              MOVE      %0, 1.5
+              MOVE      %1, %0
+              ADD       %1, 1
+              WRITE     %1
+
+              MOVE      %2, 2147483647
+              ADD       %2, 1
+              JMP       .L0
+            .L0
+              WRITE     %2
+
+              MOVE      %3, 0.5
+            .L1
+              ADD       %3, 1
+              JLT       .L1, %3, 3
+              WRITE     %3
+
+              MOVE      %4, 2.5
+              MOD       %4, 2
+              JNE       .L2, %4, 0
+              WRITE     "even"
+            .L2
+              WRITE     %4
+              RET
+        "#);
+
+        let output = Output::buffer();
+
+        Machina::new(&environment).with_output(output.clone()).call(0, 0, 0).unwrap();
+
+        assert_eq!(output.contents(), Some("2.5\n2147483648\n3.5\neven\n0\n".into()));
+    }
+
+    #[test]
+    fn type_feedback() {
+        let environment = environment(r#"
+            @entrypoint
+              MOVE      %0, 1
+              MOVE      %1, 2
+              CALL      @less, %2, %0, %1
+              WRITE     %2
+              MOVE      %0, 2.5
+              CALL      @less, %2, %0, %1
+              WRITE     %2
+              MOVE      %1, 3.5
+              CALL      @less, %2, %0, %1
+              WRITE     %2
+              RET
+
+            @less
+              LT        %0, %0, %1
+              RET       %0
+
+            @invalid
+              MOVE      %0, "a"
+              MOVE      %1, 1
+              CALL      @less, %0, %0, %1
+              RET       %0
+        "#);
+
+        let output = Output::buffer();
+
+        Machina::new(&environment).with_output(output.clone()).call(0, 0, 0).unwrap();
+
+        assert_eq!(output.contents(), Some("true\nfalse\ntrue\n".into()));
+
+        let less = &environment.functions[1].feedback;
+
+        assert_eq!(less.get(0), feedback::INTEGERS | feedback::MIXED | feedback::NUMBERS);
+
+        // Seeing only numbers doesn't skip the checks for anything else
+        let error = Machina::new(&environment).call(2, 0, 0).unwrap_err();
+
+        assert_eq!(error.error, MachinaError::InvalidOperands("lt".into(), "string".into(), "integer".into()));
+        assert_eq!(less.get(0) & feedback::OTHER, feedback::OTHER);
+    }
+
+    #[test]
+    fn packed_type_feedback() {
+        let environment = environment(r#"
+            @entrypoint
+              MOVE      %0, 1
+              MOVE      %1, 2
+              CALL      @sum, %2, %0, %1
+              MOVE      %0, 1.5
+              CALL      @sum, %2, %0, %1
+              WRITE     %2
+              RET
+
+            @sum(2)
+              MOVE      %2, %0
+              ADD       %2, %1
+              ADD       %2, %0
+              ADD       %2, 1
+              RET       %2
+        "#);
+
+        let output = Output::buffer();
+
+        Machina::new(&environment).with_output(output.clone()).call(0, 0, 0).unwrap();
+
+        assert_eq!(output.contents(), Some("6\n".into()));
+
+        // The fused `MOVE` and `ADD`, a packed register and a packed immediate
+        let sum = &environment.functions[1].feedback;
+
+        assert_eq!(sum.get(1), feedback::INTEGERS | feedback::MIXED);
+        assert_eq!(sum.get(2), feedback::INTEGERS | feedback::NUMBERS);
+        assert_eq!(sum.get(3), feedback::INTEGERS | feedback::MIXED);
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn jit_deopts_to_the_interpreter() {
+        let environment = environment(r#"
+            @entrypoint
+              MOVE      %0, 0
+              MOVE      %1, 2147483640
+            .L0
+              ADD       %1, 1
+              ADD       %0, 1
+              JLT       .L0, %0, 10
+              WRITE     %1
+              CALL      @half, %1, %1, %1
+              WRITE     %1
+              RET       %1
+
+            @half
+              DIV       %0, 2
+              RET       %0
+        "#);
+
+        let run = |threshold| {
+            let output = Output::buffer();
+            let mut machina = Machina::new(&environment).with_output(output.clone()).with_jit(threshold);
+            machina.call(0, 0, 0).unwrap();
+            (output.contents().unwrap(), machina.jit.compiled())
+        };
+
+        let expected = "2147483650\n1073741825\n".to_string();
+
+        assert_eq!(run(None), (expected.clone(), 0));
+        assert_eq!(run(Some(2)), (expected.clone(), 1));
+        assert_eq!(run(Some(0)), (expected, 2));
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn jit_stack_trace() {
+        let environment = environment(r#"
+            @entrypoint
+              MOVE      %0, 1
+              CALL      @fail, %0, %0, %0
+              RET       %0
+
+            @fail
+              MOVE      %1, 0
+              GET       %0, %0, %1
+              RET       %0
+        "#);
+
+        let interpreted = Machina::new(&environment).with_jit(None).call(0, 0, 0).unwrap_err();
+        let compiled = Machina::new(&environment).with_jit(Some(0)).call(0, 0, 0).unwrap_err();
+
+        assert_eq!(compiled, interpreted);
+        assert_eq!(compiled.trace[0].index, 1);
+    }
+
+    #[test]
+    fn stack_trace() {
+        let environment = environment(r#"
+            @entrypoint
+              MOVE      %0, 1
+              CALL      @divide, %0, %0, %0
+              RET       %0
+
+            @divide
+              MOVE      %1, 0
+              DIV       %0, %1
+              RET       %0
+        "#);
+
+        let error = Machina::new(&environment).call(0, 0, 0).unwrap_err();
+
+        assert_eq!(error.error, MachinaError::DivisionByZero);
+
+        let frames: Vec<_> = error.trace.iter()
+            .map(|frame| (frame.function.as_str(), frame.index, frame.location.clone()))
+            .collect();
+
+        assert_eq!(frames, vec![
+            ("divide", 1, Some("<input>:9".into())),
+            ("entrypoint", 1, Some("<input>:4".into())),
+        ]);
+    }
+
+    #[test]
+    fn frames_do_not_overlap() {
+        let environment = environment(r#"
+            @entrypoint
+              MOVE      %0, 1
+              MOVE      %7, 2
+              CALL      @clobber, %0, %0, %0
+              ADD       %0, %7
+              RET       %0
+
+            @clobber
+              MOVE      %0, 10
+              MOVE      %5, 100
+              RET       %0
+        "#);
+
+        let value = Machina::new(&environment).call(0, 0, 0).unwrap();
+
+        assert_eq!(value, Value::from(12));
+    }
+
+    #[test]
+    fn data_section() {
+        let environment = environment(r#"
+            .data
+              primes = [2, 3, 5, 7]
+              names  = ["ada", "grace"]
+              buffer = bytes "hi"
+
+            @entrypoint
+              MOVE      %0, primes
+              LEN       %1, %0
+              GET       %2, %0, 3
+              GET       %3, names, 1
+              GET       %4, buffer, 1
+              LEN       %5, buffer
+              GET       %6, %0, 4
+              RET       %0
+        "#);
+
+        let mut machina = Machina::new(&environment);
+        let error = machina.call(0, 0, 0).unwrap_err();
+
+        assert_eq!(machina.registers[1], Value::from(4));
+        assert_eq!(machina.registers[2], Value::from(7));
+        assert_eq!(machina.export(machina.registers[3]), Message::String("grace".into()));
+        assert_eq!(machina.registers[4], Value::from('i' as i32));
+        assert_eq!(machina.registers[5], Value::from(2));
+        assert_eq!(machina.display(machina.registers[0]).to_string(), "[2, 3, 5, 7]");
+        assert_eq!(error.error, MachinaError::IndexOutOfBounds(4, 4));
+    }
+
+    #[test]
+    fn invalid_operands() {
+        let environment = environment(r#"
+            @entrypoint
+              MOVE      %0, "a"
+              ADD       %0, 1
+              RET       %0
+        "#);
+
+        let error = Machina::new(&environment).call(0, 0, 0).unwrap_err();
+
+        assert_eq!(error.error.to_string(), "Cannot apply `add` to string and integer");
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{
+    bytecode::{
+        Function,
+        OpCode,
+        Register,
+    },
+    encoding::{
+        self,
+        Op,
+        Word,
+    },
+    error::{
+        MachinaError,
+        RuntimeResult,
+    },
+    feedback,
+    value::Value,
+};
+
+use super::{
+    opcode_name,
+    Machina,
+};
+
+// The alternative to the `match` of `Machina::execute`. Every function is
+// decoded once into the handler of each of its words, when the environment
+// is created, and the loop calls straight into it through a function
+// pointer, so the jump to each op is predicted from the previous one
+// instead of from a single shared branch. The handlers and the arms of the
+// `match` are both generated from `packed_ops!`
+pub type Handler = for<'a> fn(&mut Machina<'a>, &Function, usize, Word, &mut usize) -> RuntimeResult<Option<Value>>;
+
+pub type Threaded = (Handler, Word);
+
+pub fn decode(code: &[Word]) -> Box<[Threaded]> {
+    code.iter()
+        .map(|&word| (handler(encoding::op(word)), word))
+        .collect()
+}
+
+// Returns a value once the function returns
+#[inline(always)]
+pub fn execute(machina: &mut Machina, index: usize, function: &Function, ip: &mut usize) -> RuntimeResult<Option<Value>> {
+    let environment = machina.environment;
+    let code = &environment.threaded[index];
+
+    loop {
+        let (handler, word) = code[*ip];
+        *ip += 1;
+
+        if let Some(value) = handler(machina, function, index, word, ip)? {
+            return Ok(Some(value));
+        }
+    }
+}
+
+// Each handler returns a value once the function returns
+macro_rules! handlers {
+    ($m:tt, $function:ident, $index:ident, $word:ident, $ip:ident, $a:ident, $x:ident; $($op:ident, $handler:ident => $body:expr,)*) => {
+        $(
+            #[allow(unused_variables, unreachable_code)]
+            fn $handler($m: &mut Machina, $function: &Function, $index: usize, $word: Word, $ip: &mut usize) -> RuntimeResult<Option<Value>> {
+                let $a = encoding::a($word) as usize;
+                let $x = encoding::x($word);
+                $body;
+                Ok(None)
+            }
+        )*
+
+        fn handler(op: Op) -> Handler {
+            match op {
+                $(Op::$op => $handler,)*
+            }
+        }
+    };
+}
+
+packed_ops!(handlers!(m, function, index, word, ip, a, x));
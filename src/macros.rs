@@ -1,16 +1,45 @@
-
 macro_rules! as_expr {
     ($e: expr) => { $e }
 }
 
+macro_rules! check_numeric {
+    ($self:expr, $instruction:expr, $lhs:expr, $rhs:expr) => {{
+        if !$lhs.is_numeric() || !$rhs.is_numeric() {
+            return Err(MachinaError::InvalidOperands(
+                opcode_name($instruction.opcode),
+                $self.type_name($lhs),
+                $self.type_name($rhs),
+            ).into());
+        }
+    }};
+}
+
+// Takes the fast path for the only kind of operands the instruction has
+// seen so far, otherwise records the new kind and checks everything
 macro_rules! binary_op {
-    ($self:expr, $instruction:expr, $op:tt) => {{
-        let lhs = $self.get($instruction.get(0));
-        let rhs = $self.get($instruction.get(1));
-        let val = if lhs.is_num() || rhs.is_num() {
-            Value::from(as_expr!(lhs.as_num() $op rhs.as_num()))
-        } else {
-            Value::from(as_expr!(lhs.as_int() $op rhs.as_int()))
+    ($self:expr, $function:expr, $index:expr, $instruction:expr, $op:tt) => {{
+        let (lhs, rhs) = $instruction.sources();
+        let lhs = $self.get(lhs);
+        let rhs = $self.get(rhs);
+        let val = match $function.feedback.get($index) {
+            feedback::INTEGERS if lhs.is_int() && rhs.is_int() => {
+                Value::from(as_expr!((lhs.get_int_unchecked() as i64) $op (rhs.get_int_unchecked() as i64)))
+            }
+            feedback::NUMBERS if lhs.is_num() && rhs.is_num() => {
+                Value::from(as_expr!(lhs.get_num_unchecked() $op rhs.get_num_unchecked()))
+            }
+            feedback::MIXED if lhs.is_numeric() && rhs.is_numeric() && lhs.is_num() != rhs.is_num() => {
+                Value::from(as_expr!(lhs.as_num() $op rhs.as_num()))
+            }
+            _ => {
+                $function.feedback.record($index, feedback::types(lhs, rhs));
+                check_numeric!($self, $instruction, lhs, rhs);
+                if lhs.is_num() || rhs.is_num() {
+                    Value::from(as_expr!(lhs.as_num() $op rhs.as_num()))
+                } else {
+                    Value::from(as_expr!(lhs.as_int() $op rhs.as_int()))
+                }
+            }
         };
         $self.set($instruction.register(0), val);
     }};
@@ -18,8 +47,10 @@ macro_rules! binary_op {
 
 macro_rules! integer_op {
     ($self:expr, $instruction:expr, $op:tt) => {{
-        let lhs = $self.get($instruction.get(0));
-        let rhs = $self.get($instruction.get(1));
+        let (lhs, rhs) = $instruction.sources();
+        let lhs = $self.get(lhs);
+        let rhs = $self.get(rhs);
+        check_numeric!($self, $instruction, lhs, rhs);
         let val = Value::from(as_expr!(lhs.as_int() $op rhs.as_int()));
         $self.set($instruction.register(0), val);
     }};
@@ -28,6 +59,9 @@ macro_rules! integer_op {
 macro_rules! unary_op {
     ($self:expr, $instruction:expr, $op:tt) => {{
         let rhs = $self.get($instruction.get(0));
+        if !rhs.is_numeric() {
+            return Err(MachinaError::InvalidOperand(opcode_name($instruction.opcode), $self.type_name(rhs)).into());
+        }
         let val = Value::from(as_expr!($op rhs.as_int()));
         $self.set($instruction.register(0), val);
     }};
@@ -41,4 +75,199 @@ macro_rules! jump_op {
             $ip = $instruction.position(0) as usize;
         }
     }};
-}
\ No newline at end of file
+}
+
+// Integers take the fast path, anything else (including the errors) runs
+// the unpacked instruction. Both paths record what they saw
+macro_rules! packed_op {
+    ($self:expr, $function:expr, $ip:expr, $a:expr, $lhs:expr, $rhs:expr, $op:tt) => {{
+        let lhs = $lhs;
+        let rhs = $rhs;
+        if lhs.is_int() && rhs.is_int() {
+            $function.feedback.record(*$ip - 1, feedback::INTEGERS);
+            let val = Value::from(as_expr!(lhs.get_int_unchecked() as i64 $op rhs.get_int_unchecked() as i64));
+            $self.store($a, val);
+        } else {
+            $self.step($function, $ip)?;
+        }
+    }};
+}
+
+macro_rules! packed_jump {
+    ($self:expr, $index:expr, $word:expr, $ip:expr, $a:expr, $rhs:expr, $op:tt) => {{
+        let lhs = $self.load($a);
+        let rhs = $rhs;
+        let taken = if lhs.is_int() && rhs.is_int() {
+            as_expr!(lhs.get_int_unchecked() $op rhs.get_int_unchecked())
+        } else {
+            as_expr!(lhs $op rhs)
+        };
+        if taken {
+            jump_to!($self, $index, $ip, encoding::b($word) as usize);
+        }
+    }};
+}
+
+// With the jit, a loop that got hot leaves the interpreter from its back edge
+macro_rules! jump_to {
+    ($self:expr, $index:expr, $ip:expr, $target:expr) => {{
+        let target = $target;
+        #[cfg(feature = "jit")]
+        {
+            if target < *$ip && $self.jit.looped($index) {
+                *$ip = target;
+                return Ok(None);
+            }
+        }
+        *$ip = target;
+    }};
+}
+
+// The superinstructions only handle integers. Otherwise they run the first
+// instruction of the pair, and leave the second one to the next word
+macro_rules! fused_op {
+    ($self:expr, $function:expr, $ip:expr, $a:expr, $b:expr, $rhs:expr, $op:tt) => {{
+        let lhs = $self.load($b);
+        let rhs = $rhs;
+        if lhs.is_int() && rhs.is_int() {
+            $function.feedback.record(*$ip, feedback::INTEGERS);
+            let val = Value::from(as_expr!(lhs.get_int_unchecked() as i64 $op rhs.get_int_unchecked() as i64));
+            $self.store($a, val);
+            *$ip += 1;
+        } else {
+            $self.store($a, lhs);
+        }
+    }};
+}
+
+macro_rules! fused_mod {
+    ($self:expr, $index:expr, $function:expr, $word:expr, $ip:expr, $a:expr, $divisor:expr, $op:tt) => {{
+        let lhs = $self.load($a);
+        if lhs.is_int() {
+            let val = lhs.get_int_unchecked() as i64 % $divisor as i64;
+            $self.store($a, Value::from(val));
+            *$ip += 1;
+            if as_expr!(val $op 0) {
+                jump_to!($self, $index, $ip, encoding::b($word) as usize);
+            }
+        } else {
+            $self.step($function, $ip)?;
+        }
+    }};
+}
+
+macro_rules! fused_jump {
+    ($self:expr, $index:expr, $function:expr, $word:expr, $ip:expr, $a:expr, $rhs:expr, $op:tt) => {{
+        let lhs = $self.load($a);
+        if lhs.is_int() {
+            $function.feedback.record(*$ip - 1, feedback::INTEGERS);
+            let step = encoding::xl($word) as i16 as i64;
+            $self.store($a, Value::from(lhs.get_int_unchecked() as i64 + step));
+            *$ip += 1;
+            packed_jump!($self, $index, $word, $ip, $a, $rhs, $op);
+        } else {
+            $self.step($function, $ip)?;
+        }
+    }};
+}
+
+// Every packed op and what it does, written once for both the `match` of
+// `Machina::execute` and the handlers of `threaded.rs`. Each entry is passed
+// on to `$dispatch` as `Op, handler => body`, with the names the bodies use
+macro_rules! packed_ops {
+    ($dispatch:ident!($m:tt, $function:ident, $index:ident, $word:ident, $ip:ident, $a:ident, $x:ident)) => {
+        $dispatch! { $m, $function, $index, $word, $ip, $a, $x;
+            MoveRR, move_rr => $m.store($a, $m.load($x as usize)),
+            MoveRI, move_ri => $m.store($a, Value::from($x as i32)),
+            MoveRK, move_rk => $m.store($a, $m.constants[$x as usize]),
+            AddRR, add_rr => packed_op!($m, $function, $ip, $a, $m.load($a), $m.load($x as usize), +),
+            AddRI, add_ri => packed_op!($m, $function, $ip, $a, $m.load($a), Value::from($x as i32), +),
+            AddRK, add_rk => packed_op!($m, $function, $ip, $a, $m.load($a), $m.constants[$x as usize], +),
+            SubRR, sub_rr => packed_op!($m, $function, $ip, $a, $m.load($a), $m.load($x as usize), -),
+            SubRI, sub_ri => packed_op!($m, $function, $ip, $a, $m.load($a), Value::from($x as i32), -),
+            SubRK, sub_rk => packed_op!($m, $function, $ip, $a, $m.load($a), $m.constants[$x as usize], -),
+            MulRR, mul_rr => packed_op!($m, $function, $ip, $a, $m.load($a), $m.load($x as usize), *),
+            MulRI, mul_ri => packed_op!($m, $function, $ip, $a, $m.load($a), Value::from($x as i32), *),
+            MulRK, mul_rk => packed_op!($m, $function, $ip, $a, $m.load($a), $m.constants[$x as usize], *),
+            AddRRR, add_rrr => packed_op!($m, $function, $ip, $a, $m.load(encoding::b($word) as usize), $m.load($x as usize), +),
+            AddRRI, add_rri => packed_op!($m, $function, $ip, $a, $m.load(encoding::b($word) as usize), Value::from($x as i32), +),
+            SubRRR, sub_rrr => packed_op!($m, $function, $ip, $a, $m.load(encoding::b($word) as usize), $m.load($x as usize), -),
+            SubRRI, sub_rri => packed_op!($m, $function, $ip, $a, $m.load(encoding::b($word) as usize), Value::from($x as i32), -),
+            MulRRR, mul_rrr => packed_op!($m, $function, $ip, $a, $m.load(encoding::b($word) as usize), $m.load($x as usize), *),
+            MulRRI, mul_rri => packed_op!($m, $function, $ip, $a, $m.load(encoding::b($word) as usize), Value::from($x as i32), *),
+            JLtRR, jlt_rr => packed_jump!($m, $index, $word, $ip, $a, $m.load($x as usize), <),
+            JLtRI, jlt_ri => packed_jump!($m, $index, $word, $ip, $a, Value::from($x as i32), <),
+            JLeRR, jle_rr => packed_jump!($m, $index, $word, $ip, $a, $m.load($x as usize), <=),
+            JLeRI, jle_ri => packed_jump!($m, $index, $word, $ip, $a, Value::from($x as i32), <=),
+            JGtRR, jgt_rr => packed_jump!($m, $index, $word, $ip, $a, $m.load($x as usize), >),
+            JGtRI, jgt_ri => packed_jump!($m, $index, $word, $ip, $a, Value::from($x as i32), >),
+            JGeRR, jge_rr => packed_jump!($m, $index, $word, $ip, $a, $m.load($x as usize), >=),
+            JGeRI, jge_ri => packed_jump!($m, $index, $word, $ip, $a, Value::from($x as i32), >=),
+            JEqRR, jeq_rr => packed_jump!($m, $index, $word, $ip, $a, $m.load($x as usize), ==),
+            JEqRI, jeq_ri => packed_jump!($m, $index, $word, $ip, $a, Value::from($x as i32), ==),
+            JNeRR, jne_rr => packed_jump!($m, $index, $word, $ip, $a, $m.load($x as usize), !=),
+            JNeRI, jne_ri => packed_jump!($m, $index, $word, $ip, $a, Value::from($x as i32), !=),
+            Jmp, jmp => jump_to!($m, $index, $ip, encoding::b($word) as usize),
+            Call, call => {
+                let (first, last) = ($x as Register, ($x >> 16) as Register);
+
+                if first > last {
+                    return Err(MachinaError::InvalidRange(opcode_name(OpCode::Call)).into());
+                }
+
+                let val = $m.call(encoding::b($word) as usize, first, last)?;
+
+                if $m.halted {
+                    return Ok(Some(Value::null()));
+                }
+
+                $m.store($a, val);
+            },
+            Ret, ret => return Ok(Some($m.load($a))),
+            Add3RR, add3_rr => fused_op!($m, $function, $ip, $a, encoding::b($word) as usize, $m.load($x as usize), +),
+            Add3RI, add3_ri => fused_op!($m, $function, $ip, $a, encoding::b($word) as usize, Value::from($x as i32), +),
+            Sub3RR, sub3_rr => fused_op!($m, $function, $ip, $a, encoding::b($word) as usize, $m.load($x as usize), -),
+            Sub3RI, sub3_ri => fused_op!($m, $function, $ip, $a, encoding::b($word) as usize, Value::from($x as i32), -),
+            Mul3RR, mul3_rr => fused_op!($m, $function, $ip, $a, encoding::b($word) as usize, $m.load($x as usize), *),
+            Mul3RI, mul3_ri => fused_op!($m, $function, $ip, $a, encoding::b($word) as usize, Value::from($x as i32), *),
+            ModJEqZ, mod_jeq_z => fused_mod!($m, $index, $function, $word, $ip, $a, $x as i32, ==),
+            ModJNeZ, mod_jne_z => fused_mod!($m, $index, $function, $word, $ip, $a, $x as i32, !=),
+            AddJLtRR, add_jlt_rr => fused_jump!($m, $index, $function, $word, $ip, $a, $m.load(encoding::xh($word) as usize), <),
+            AddJLtRI, add_jlt_ri => fused_jump!($m, $index, $function, $word, $ip, $a, Value::from(encoding::xh($word) as i16 as i32), <),
+            AddJLeRR, add_jle_rr => fused_jump!($m, $index, $function, $word, $ip, $a, $m.load(encoding::xh($word) as usize), <=),
+            AddJLeRI, add_jle_ri => fused_jump!($m, $index, $function, $word, $ip, $a, Value::from(encoding::xh($word) as i16 as i32), <=),
+            AddJNeRR, add_jne_rr => fused_jump!($m, $index, $function, $word, $ip, $a, $m.load(encoding::xh($word) as usize), !=),
+            AddJNeRI, add_jne_ri => fused_jump!($m, $index, $function, $word, $ip, $a, Value::from(encoding::xh($word) as i16 as i32), !=),
+            AddJmp, add_jmp => {
+                let lhs = $m.load($a);
+
+                if lhs.is_int() {
+                    $function.feedback.record(*$ip - 1, feedback::INTEGERS);
+                    $m.store($a, Value::from(lhs.get_int_unchecked() as i64 + $x as i32 as i64));
+                    jump_to!($m, $index, $ip, encoding::b($word) as usize);
+                } else {
+                    $m.step($function, $ip)?;
+                }
+            },
+            Generic, generic => {
+                if let Some(value) = $m.step($function, $ip)? {
+                    return Ok(Some(value));
+                }
+            },
+        }
+    };
+}
+
+// The `match` of `Machina::execute`
+#[cfg(any(not(feature = "threaded"), feature = "jit"))]
+macro_rules! dispatch {
+    ($m:tt, $function:ident, $index:ident, $word:ident, $ip:ident, $a:ident, $x:ident; $($op:ident, $handler:ident => $body:expr,)*) => {
+        match encoding::op($word) {
+            $(
+                Op::$op => {
+                    $body;
+                }
+            )*
+        }
+    };
+}
@@ -1,50 +1,320 @@
-use std::fs;
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
 
 use machina::{
+    actor::{
+        self,
+        Message,
+    },
+    aot,
     bytecode::{
         Module,
     },
+    error::MachinaError,
+    io::Output,
+    linker::Linker,
+    objfile,
+    optimizer::Optimizer,
     machina::{
         Environment,
         Machina,
     },
-    parser::Parser
+    parser::Parser,
+    value::Value,
 };
 
+const DEFAULT_ENTRY: &str = "entrypoint";
+
+struct Options {
+    entry: String,
+    optimize: bool,
+}
+
 fn main() {
-    let args = std::env::args().collect::<Vec<String>>();
-    if args.len() <= 1 {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+
+    let mut options = Options {
+        entry: DEFAULT_ENTRY.into(),
+        optimize: false,
+    };
+
+    let mut args = &args[..];
+
+    match args.first().map(|arg| arg.as_str()) {
+        Some("build") => process::exit(build(&args[1..])),
+        Some("link") => process::exit(link(&args[1..])),
+        _ => {}
+    }
+
+    while let Some(arg) = args.first() {
+        match arg.as_str() {
+            "--entry" if args.len() > 1 => {
+                options.entry = args[1].trim_start_matches('@').into();
+                args = &args[2..];
+            }
+            "-O" => {
+                options.optimize = true;
+                args = &args[1..];
+            }
+            _ => break
+        }
+    }
+
+    if args.is_empty() {
         println!("Machina v {}", env!("CARGO_PKG_VERSION"));
-        println!("Use 'machina <file name>' to compile and/or execute a file");
+        println!("Use 'machina [-O] [--entry <function>] <file name> [arguments...]' to compile and/or execute a file");
+        println!("Use 'machina build <file name> [-o <object>]' to assemble a file into an object");
+        println!("Use 'machina build --emit c <file name> [-o <output>]' to translate a program into C");
+        println!("Use 'machina link <objects...> -o <output>' to link objects into one");
     } else {
-        file(args.get(1).unwrap().to_string());
+        let code = file(args[0].to_string(), &args[1..], &options);
+        process::exit(code);
+    }
+}
+
+fn file(file: String, args: &[String], options: &Options) -> i32 {
+    let mut linker = Linker::new();
+
+    if !load(Path::new(&file), &mut linker, &mut HashSet::new()) {
+        return 1;
+    }
+
+    match linker.link() {
+        Ok(mut module) => {
+            if options.optimize {
+                Optimizer::default().optimize(&mut module);
+            }
+
+            eval(module, args, options)
+        }
+        Err(diagnostics) => {
+            diagnostics.emit(&file, "");
+            1
+        }
+    }
+}
+
+// Splits `<inputs...> [-o <output>]`
+fn outputs(args: &[String]) -> (Vec<&String>, Option<&String>) {
+    let mut inputs = vec![];
+    let mut output = None;
+
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            output = args.next();
+        } else {
+            inputs.push(arg);
+        }
+    }
+
+    (inputs, output)
+}
+
+// Assembles a single file, its imports are only resolved when linking.
+// With `--emit c` it is linked with its imports and translated into C
+fn build(args: &[String]) -> i32 {
+    let (emit, args) = match args {
+        [flag, kind, rest @ ..] if flag == "--emit" && kind == "c" => (true, rest),
+        [flag, ..] if flag == "--emit" => {
+            eprintln!("Use 'machina build --emit c <file name> [-o <output>]'");
+            return 1;
+        }
+        _ => (false, args),
+    };
+
+    let (inputs, output) = outputs(args);
+
+    let file = match inputs[..] {
+        [file] => Path::new(file),
+        _ => {
+            eprintln!("Use 'machina build [--emit c] <file name> [-o <output>]'");
+            return 1;
+        }
+    };
+
+    if emit {
+        let output = output.map(PathBuf::from).unwrap_or_else(|| file.with_extension("c"));
+        return translate(file, &output);
+    }
+
+    let module = match module(file) {
+        Some(module) => module,
+        None => return 1,
+    };
+
+    let output = output.map(PathBuf::from).unwrap_or_else(|| file.with_extension("mo"));
+
+    save(&output, &module)
+}
+
+fn translate(file: &Path, output: &Path) -> i32 {
+    let mut linker = Linker::new();
+
+    if !load(file, &mut linker, &mut HashSet::new()) {
+        return 1;
+    }
+
+    let module = match linker.link() {
+        Ok(module) => module,
+        Err(diagnostics) => {
+            diagnostics.emit(&file.to_string_lossy(), "");
+            return 1;
+        }
+    };
+
+    let entry = match module.function(DEFAULT_ENTRY) {
+        Some(entry) => entry,
+        None => {
+            eprintln!("{}", MachinaError::EntryPointNotFound(DEFAULT_ENTRY.into()));
+            return 1;
+        }
+    };
+
+    match fs::write(output, aot::emit(&module, entry)) {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("error: Couldn't write the file `{}`: {}", output.display(), error);
+            1
+        }
     }
 }
 
-fn file(file: String) {
-    let input = fs::read_to_string(file.clone()).expect("Couldn't open the file");
-    exec(input);
+fn link(args: &[String]) -> i32 {
+    let (inputs, output) = outputs(args);
+
+    let output = match output {
+        Some(output) if !inputs.is_empty() => Path::new(output),
+        _ => {
+            eprintln!("Use 'machina link <objects...> -o <output>'");
+            return 1;
+        }
+    };
+
+    let mut linker = Linker::new();
+
+    for input in inputs {
+        match module(Path::new(input)) {
+            Some(module) => linker.add(module),
+            None => return 1,
+        }
+    }
+
+    match linker.link() {
+        Ok(module) => save(output, &module),
+        Err(diagnostics) => {
+            diagnostics.emit(&output.to_string_lossy(), "");
+            1
+        }
+    }
 }
 
-fn exec(source: String) {
-    match Parser::new(&source).parse() {
-        Ok(module) => {
-            eval(module)
+fn save(path: &Path, module: &Module) -> i32 {
+    match fs::write(path, objfile::write(module)) {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("error: Couldn't write the file `{}`: {}", path.display(), error);
+            1
         }
+    }
+}
+
+// Reads either an object file or a source file
+fn module(path: &Path) -> Option<Module> {
+    let file = path.to_string_lossy().into_owned();
+
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
         Err(error) => {
-            eprintln!("{}", error)
+            eprintln!("error: Couldn't open the file `{}`: {}", file, error);
+            return None;
+        }
+    };
+
+    if objfile::is_object(&bytes) {
+        return match objfile::read(&bytes) {
+            Ok(module) => Some(module),
+            Err(error) => {
+                eprintln!("error: {} in `{}`", error, file);
+                None
+            }
+        };
+    }
+
+    let source = String::from_utf8_lossy(&bytes);
+
+    match Parser::new(&source).with_file(&file).parse() {
+        Ok(module) => Some(module),
+        Err(diagnostics) => {
+            diagnostics.emit(&file, &source);
+            None
         }
     }
 }
 
-fn eval(module: Module) {
+// Reads `path` and every file it imports, relative to the importing file,
+// loading each of them only once
+fn load(path: &Path, linker: &mut Linker, loaded: &mut HashSet<PathBuf>) -> bool {
+    if !loaded.insert(path.canonicalize().unwrap_or_else(|_| path.to_path_buf())) {
+        return true;
+    }
+
+    let module = match module(path) {
+        Some(module) => module,
+        None => return false,
+    };
+
+    let imports = module.imports
+        .iter()
+        .map(|import| path.parent().unwrap_or(Path::new("")).join(import))
+        .collect::<Vec<_>>();
+
+    linker.add(module);
 
-    let Module { functions, constants } = module;
+    imports.iter().all(|import| load(import, linker, loaded))
+}
+
+fn eval(module: Module, args: &[String], options: &Options) -> i32 {
 
-    let environment = Environment {
-        functions,
-        constants,
+    let entry = match module.function(&options.entry) {
+        Some(entry) => entry,
+        None => {
+            eprintln!("{}", MachinaError::EntryPointNotFound(options.entry.clone()));
+            return 1;
+        }
     };
 
-    Machina::new(&environment).call(0, 0, 0);
+    let Module { functions, constants, .. } = module;
+
+    let environment = Environment::new(functions, constants);
+
+    let output = Output::stdout();
+
+    let args = args
+        .iter()
+        .map(|arg| {
+            match arg.parse::<i64>() {
+                Ok(int) => Message::Value(Value::from(int)),
+                Err(_) => Message::String(arg.clone()),
+            }
+        })
+        .collect();
+
+    let result = actor::run(Machina::new(&environment).with_output(output.clone()), entry, args);
+
+    let _ = output.flush();
+
+    match result {
+        Ok(Message::Value(value)) if value.is_int() => value.get_int(),
+        Ok(_) => 0,
+        Err(error) => {
+            eprintln!("{}", error);
+            1
+        }
+    }
 }
@@ -1,4 +1,6 @@
-use std::{cmp::Ordering, hash::Hash, hash::Hasher, ops::Deref};
+use std::{cmp::Ordering, fmt, hash::Hash, hash::Hasher, ops::Deref};
+
+use crate::value::Value;
 
 #[derive(Debug, Clone, Hash, PartialOrd, PartialEq)]
 pub enum Object {
@@ -14,22 +16,76 @@ pub enum Object {
 
     // Object(HashMap<String, Box<Value>>),
 
-    // List(Vec<Value>),
+    List(Vec<Value>),
+
+    Bytes(Vec<u8>),
 
     // Tuple(Vec<Value>),
 
     Null
 }
 
+impl Object {
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::String(_) => "string",
+            Object::Number(_) => "number",
+            Object::Integer(_) => "integer",
+            Object::Boolean(_) => "boolean",
+            Object::List(_) => "list",
+            Object::Bytes(_) => "bytes",
+            Object::Null => "null",
+        }
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Object::String(string) => write!(f, "{}", string),
+            Object::Number(number) => write!(f, "{}", number.value()),
+            Object::Integer(integer) => write!(f, "{}", integer),
+            Object::Boolean(boolean) => write!(f, "{}", boolean),
+            Object::List(values) => {
+                write!(f, "[")?;
+                for (idx, value) in values.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Object::Bytes(bytes) => {
+                write!(f, "b\"")?;
+                for byte in bytes.iter() {
+                    write!(f, "{}", std::ascii::escape_default(*byte))?;
+                }
+                write!(f, "\"")
+            }
+            Object::Null => write!(f, "null"),
+        }
+    }
+}
+
 impl Default for Object {
     fn default() -> Object {
         Object::Null
     }
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialOrd, PartialEq)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Number(f64);
 
+// Numbers compare by their bits, so they can be used as keys: `NaN` equals
+// itself and `-0.0` differs from `0.0`
+impl PartialEq for Number {
+    fn eq(&self, other: &Number) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
 impl Eq for Number {}
 
 impl Hash for Number {
@@ -38,12 +94,15 @@ impl Hash for Number {
     }
 }
 
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Number) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Ord for Number {
     fn cmp(&self, other: &Number) -> Ordering {
-        match self.partial_cmp(&other) {
-            Some(ord) => ord,
-            None => unreachable!(),
-        }
+        self.0.total_cmp(&other.0)
     }
 }
 
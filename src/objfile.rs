@@ -0,0 +1,559 @@
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+};
+
+use crate::{
+    bytecode::{
+        Constant,
+        Function,
+        Instruction,
+        Module,
+        OpCode,
+        Operand,
+        SourceMap,
+    },
+    error::{
+        MachinaError,
+        Result,
+    },
+};
+
+// An assembled module, saved so it can be linked without parsing its source
+// again. Everything is little-endian, and strings and lists are prefixed by
+// their length as a u32:
+//
+//   magic, file, imports, exports, externs, relocations, constants, functions
+//
+// Calls to a function of another module are left as a placeholder in the
+// code, and the relocation table says which extern goes in each of them
+const MAGIC: &[u8; 8] = b"MACHINA\x01";
+
+const UNRESOLVED: u32 = u32::MAX;
+
+const OPCODES: &[OpCode] = &[
+    OpCode::Call,
+    OpCode::Ret,
+    OpCode::Move,
+    OpCode::Jmp,
+    OpCode::Jt,
+    OpCode::Jf,
+    OpCode::JLt,
+    OpCode::JLe,
+    OpCode::JGt,
+    OpCode::JGe,
+    OpCode::JEq,
+    OpCode::JNe,
+    OpCode::Lt,
+    OpCode::Le,
+    OpCode::Gt,
+    OpCode::Ge,
+    OpCode::Eq,
+    OpCode::Ne,
+    OpCode::Add,
+    OpCode::Sub,
+    OpCode::Mul,
+    OpCode::Div,
+    OpCode::Mod,
+    OpCode::Not,
+    OpCode::And,
+    OpCode::Or,
+    OpCode::Xor,
+    OpCode::Shl,
+    OpCode::Shr,
+    OpCode::Write,
+    OpCode::Print,
+    OpCode::Read,
+    OpCode::ReadLn,
+    OpCode::ReadInt,
+    OpCode::ReadNum,
+    OpCode::Spawn,
+    OpCode::Send,
+    OpCode::Recv,
+    OpCode::Pid,
+    OpCode::Len,
+    OpCode::Get,
+];
+
+pub fn is_object(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Relocation {
+    pub function: u32,
+    pub instruction: u32,
+    pub operand: u8,
+    pub symbol: u32,
+}
+
+// Every call into `module.externs`, in the order they appear in the code
+pub fn relocations(module: &Module) -> Vec<Relocation> {
+    let locals = module.functions.len();
+
+    let mut relocations = vec![];
+
+    for (f, function) in module.functions.iter().enumerate() {
+        for (i, instruction) in function.instructions().iter().enumerate() {
+            for (o, operand) in instruction.operands.iter().enumerate() {
+                match operand {
+                    Operand::Function(index) if *index as usize >= locals => {
+                        relocations.push(Relocation {
+                            function: f as u32,
+                            instruction: i as u32,
+                            operand: o as u8,
+                            symbol: (*index as usize - locals) as u32,
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    relocations
+}
+
+pub fn write(module: &Module) -> Vec<u8> {
+    let mut writer = Writer { bytes: MAGIC.to_vec() };
+
+    let locals = module.functions.len();
+
+    writer.string(&module.file);
+
+    writer.u32(module.imports.len() as u32);
+    for import in module.imports.iter() {
+        writer.string(import);
+    }
+
+    let mut exports = module.symbols.iter().collect::<Vec<_>>();
+    exports.sort_by_key(|(_, index)| **index);
+
+    writer.u32(exports.len() as u32);
+    for (name, index) in exports {
+        writer.string(name);
+        writer.u32(*index as u32);
+    }
+
+    writer.u32(module.externs.len() as u32);
+    for name in module.externs.iter() {
+        writer.string(name);
+    }
+
+    let relocations = relocations(module);
+
+    writer.u32(relocations.len() as u32);
+    for relocation in relocations {
+        writer.u32(relocation.function);
+        writer.u32(relocation.instruction);
+        writer.u8(relocation.operand);
+        writer.u32(relocation.symbol);
+    }
+
+    writer.u32(module.constants.len() as u32);
+    for constant in module.constants.iter() {
+        writer.constant(constant);
+    }
+
+    writer.u32(module.functions.len() as u32);
+    for function in module.functions.iter() {
+        writer.string(&function.name);
+        writer.u8(function.arity.is_some() as u8);
+        writer.u8(function.arity.unwrap_or_default());
+        writer.u8(function.locals);
+
+        writer.u32(function.instructions().len() as u32);
+        for instruction in function.instructions().iter() {
+            let opcode = OPCODES.iter().position(|opcode| *opcode == instruction.opcode).unwrap();
+            writer.u8(opcode as u8);
+
+            for operand in instruction.operands.iter() {
+                let (tag, value) = match *operand {
+                    Operand::None => (0, 0),
+                    Operand::Immediate(immediate) => (1, immediate as u32),
+                    Operand::Position(position) => (2, position as u32),
+                    Operand::Register(register) => (3, register as u32),
+                    Operand::Function(index) if index as usize >= locals => (4, UNRESOLVED),
+                    Operand::Function(index) => (4, index as u32),
+                    Operand::Constant(index) => (5, index as u32),
+                    Operand::WideConstant(index) => (6, index),
+                };
+
+                writer.u8(tag);
+                writer.u32(value);
+            }
+        }
+
+        writer.string(&function.source_map.file);
+
+        let lines = function.source_map.entries();
+
+        writer.u32(lines.len() as u32);
+        for (index, line) in lines {
+            writer.u32(*index);
+            writer.u32(*line);
+        }
+    }
+
+    writer.bytes
+}
+
+pub fn read(bytes: &[u8]) -> Result<Module> {
+    if !is_object(bytes) {
+        return Err(MachinaError::InvalidObject("not a machina object file".into()));
+    }
+
+    let mut reader = Reader { bytes, offset: MAGIC.len() };
+
+    let file = reader.string()?;
+
+    let imports = reader.list(|reader| reader.string())?;
+
+    let symbols = reader
+        .list(|reader| Ok((reader.string()?, reader.u32()? as usize)))?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+    let externs = reader.list(|reader| reader.string())?;
+
+    let relocations = reader.list(|reader| {
+        Ok(Relocation {
+            function: reader.u32()?,
+            instruction: reader.u32()?,
+            operand: reader.u8()?,
+            symbol: reader.u32()?,
+        })
+    })?;
+
+    let constants = reader.list(|reader| reader.constant())?;
+
+    let mut functions = reader.list(|reader| {
+        let name = reader.string()?;
+        let declared = reader.u8()? != 0;
+        let arity = Some(reader.u8()?).filter(|_| declared);
+        let locals = reader.u8()?;
+
+        let instructions = reader.list(|reader| {
+            let opcode = match OPCODES.get(reader.u8()? as usize) {
+                Some(opcode) => *opcode,
+                None => return Err(MachinaError::InvalidObject("unknown opcode".into())),
+            };
+
+            let mut operands = [Operand::None; 4];
+
+            for operand in operands.iter_mut() {
+                let tag = reader.u8()?;
+                let value = reader.u32()?;
+
+                *operand = match tag {
+                    0 => Operand::None,
+                    1 => Operand::Immediate(value as i32),
+                    2 => Operand::Position(value as u16),
+                    3 => Operand::Register(value as u16),
+                    4 => Operand::Function(value as u16),
+                    5 => Operand::Constant(value as u16),
+                    6 => Operand::WideConstant(value),
+                    tag => return Err(MachinaError::InvalidObject(format!("unknown operand tag {}", tag))),
+                };
+            }
+
+            Ok(Instruction::new(opcode, operands))
+        })?;
+
+        let mut source_map = SourceMap::new(reader.string()?);
+
+        for (index, line) in reader.list(|reader| Ok((reader.u32()?, reader.u32()?)))? {
+            source_map.add(index as usize, line as usize);
+        }
+
+        Ok(Function::new(name, locals, instructions)
+            .with_arity(arity)
+            .with_source_map(source_map))
+    })?;
+
+    let locals = functions.len();
+
+    if relocations.iter().any(|relocation| relocation.function as usize >= locals) {
+        return Err(MachinaError::InvalidObject("relocation out of bounds".into()));
+    }
+
+    for (index, function) in functions.iter_mut().enumerate() {
+        function.edit(|instructions| {
+            for relocation in relocations.iter().filter(|relocation| relocation.function as usize == index) {
+                let operand = instructions
+                    .get_mut(relocation.instruction as usize)
+                    .and_then(|instruction| instruction.operands.get_mut(relocation.operand as usize));
+
+                match operand {
+                    Some(operand) if (relocation.symbol as usize) < externs.len() => {
+                        *operand = Operand::Function((locals + relocation.symbol as usize) as u16);
+                    }
+                    _ => return Err(MachinaError::InvalidObject("relocation out of bounds".into())),
+                }
+            }
+
+            Ok(())
+        })?;
+    }
+
+    // Every call left is either local or relocated, so the linker can
+    // follow the operands without checking them again
+    let operands = functions
+        .iter()
+        .flat_map(|function| function.instructions().iter())
+        .flat_map(|instruction| instruction.operands.iter());
+
+    for operand in operands {
+        let valid = match *operand {
+            Operand::Function(index) => (index as usize) < locals + externs.len(),
+            Operand::Constant(index) => (index as usize) < constants.len(),
+            Operand::WideConstant(index) => (index as usize) < constants.len(),
+            _ => true,
+        };
+
+        if !valid {
+            return Err(MachinaError::InvalidObject("operand out of bounds".into()));
+        }
+    }
+
+    Ok(Module { file, functions, constants, symbols, imports, externs })
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn constant(&mut self, constant: &Constant) {
+        match constant {
+            Constant::String(string) => {
+                self.u8(0);
+                self.string(string);
+            }
+            Constant::Number(number) => {
+                self.u8(1);
+                self.bytes.extend_from_slice(&number.value().to_le_bytes());
+            }
+            Constant::Integer(integer) => {
+                self.u8(2);
+                self.bytes.extend_from_slice(&integer.to_le_bytes());
+            }
+            Constant::List(constants) => {
+                self.u8(3);
+                self.u32(constants.len() as u32);
+                for constant in constants.iter() {
+                    self.constant(constant);
+                }
+            }
+            Constant::Bytes(bytes) => {
+                self.u8(4);
+                self.u32(bytes.len() as u32);
+                self.bytes.extend_from_slice(bytes);
+            }
+            Constant::Char(chr) => {
+                self.u8(5);
+                self.u32(*chr as u32);
+            }
+            Constant::Boolean(boolean) => {
+                self.u8(6);
+                self.u8(*boolean as u8);
+            }
+            Constant::Null => {
+                self.u8(7);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        match self.bytes.get(self.offset..self.offset + count) {
+            Some(bytes) => {
+                self.offset += count;
+                Ok(bytes)
+            }
+            None => Err(MachinaError::InvalidObject("unexpected end of file".into())),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let length = self.u32()? as usize;
+
+        String::from_utf8(self.take(length)?.to_vec())
+            .map_err(|_| MachinaError::InvalidObject("invalid string".into()))
+    }
+
+    fn constant(&mut self) -> Result<Constant> {
+        match self.u8()? {
+            0 => Ok(Constant::String(self.string()?)),
+            1 => {
+                let bytes = self.take(8)?;
+                Ok(Constant::Number(f64::from_le_bytes(bytes.try_into().unwrap()).into()))
+            }
+            2 => {
+                let bytes = self.take(8)?;
+                Ok(Constant::Integer(i64::from_le_bytes(bytes.try_into().unwrap())))
+            }
+            3 => Ok(Constant::List(self.list(|reader| reader.constant())?)),
+            4 => {
+                let length = self.u32()? as usize;
+                Ok(Constant::Bytes(self.take(length)?.to_vec()))
+            }
+            5 => {
+                char::from_u32(self.u32()?)
+                    .map(Constant::Char)
+                    .ok_or_else(|| MachinaError::InvalidObject("invalid char".into()))
+            }
+            6 => Ok(Constant::Boolean(self.u8()? != 0)),
+            7 => Ok(Constant::Null),
+            tag => Err(MachinaError::InvalidObject(format!("unknown constant tag {}", tag))),
+        }
+    }
+
+    fn list<T, F: FnMut(&mut Reader<'a>) -> Result<T>>(&mut self, mut item: F) -> Result<Vec<T>> {
+        let count = self.u32()?;
+
+        (0..count).map(|_| item(self)).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        linker::Linker,
+        parser::Parser,
+    };
+
+    #[test]
+    fn round_trip() {
+        let module = Parser::new(r#"
+            import "lib.machina"
+            .extern @external
+
+            @entrypoint
+              MOVE      %0, "hello"
+              MOVE      %1, 1.5
+              MOVE      %2, 'c'
+              MOVE      %3, false
+              MOVE      %3, null
+              CALL      @helper, %0, %0, %1
+              CALL      @external, %0, %0, %1
+              RET       %0
+
+            @helper(2) locals 4
+              RET       %0
+        "#).with_file("main.machina").parse().unwrap();
+
+        let bytes = write(&module);
+        let read = read(&bytes).unwrap();
+
+        assert_eq!(read.file, "main.machina");
+        assert_eq!(read.imports, vec!["lib.machina".to_string()]);
+        assert_eq!(read.externs, vec!["external".to_string()]);
+        assert_eq!(read.symbols, module.symbols);
+        assert_eq!(read.constants, module.constants);
+        assert_eq!(relocations(&read), vec![
+            Relocation { function: 0, instruction: 6, operand: 0, symbol: 0 },
+        ]);
+
+        for (read, function) in read.functions.iter().zip(module.functions.iter()) {
+            assert_eq!(read.name, function.name);
+            assert_eq!(read.arity, function.arity);
+            assert_eq!(read.locals, function.locals);
+            assert_eq!(read.instructions(), function.instructions());
+            assert_eq!(read.source_map, function.source_map);
+        }
+    }
+
+    #[test]
+    fn link_objects() {
+        let main = Parser::new(r#"
+            import "lib.machina"
+            .extern @double
+
+            @entrypoint
+              CALL      @double, %0, %0, %0
+              RET       %0
+        "#).parse().unwrap();
+
+        let lib = Parser::new(r#"
+            @double
+              ADD       %0, %0
+              RET       %0
+        "#).parse().unwrap();
+
+        let mut linker = Linker::new();
+        linker.add(read(&write(&main)).unwrap());
+        linker.add(read(&write(&lib)).unwrap());
+
+        let module = linker.link().unwrap();
+
+        assert_eq!(module.functions[0].instructions()[0].get(0), Operand::Function(1));
+    }
+
+    #[test]
+    fn reject_invalid_objects() {
+        assert!(read(b"@entrypoint").is_err());
+        assert!(read(b"MACHINA\x01\x05").is_err());
+    }
+
+    #[test]
+    fn reject_operands_out_of_bounds() {
+        let module = Parser::new(r#"
+            @entrypoint
+              MOVE      %0, "hello"
+              CALL      @helper, %0, %0, %0
+              RET       %0
+
+            @helper
+              RET       %0
+        "#).parse().unwrap();
+
+        let bytes = write(&module);
+
+        // The operand tag followed by its value
+        let patch = |from: [u8; 5], to: [u8; 5]| {
+            let at = bytes.windows(5).position(|window| window == from).unwrap();
+            let mut bytes = bytes.clone();
+            bytes[at .. at + 5].copy_from_slice(&to);
+            read(&bytes)
+        };
+
+        let error = MachinaError::InvalidObject("operand out of bounds".into());
+
+        assert!(read(&bytes).is_ok());
+        assert_eq!(patch([4, 1, 0, 0, 0], [4, 0xFF, 0xFF, 0xFF, 0xFF]).unwrap_err(), error);
+        assert_eq!(patch([5, 0, 0, 0, 0], [5, 1, 0, 0, 0]).unwrap_err(), error);
+    }
+}
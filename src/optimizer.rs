@@ -0,0 +1,580 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::bytecode::{
+    Function,
+    Immediate,
+    Instruction,
+    Module,
+    OpCode,
+    Operand,
+    Register,
+    SourceMap,
+};
+
+// Passes run one after the other until none of them changes the function,
+// or `MAX_ROUNDS` is reached
+const MAX_ROUNDS: usize = 16;
+
+pub trait Pass {
+    fn name(&self) -> &'static str;
+
+    // Returns whether the function was changed
+    fn run(&self, function: &mut Function) -> bool;
+}
+
+pub struct Optimizer {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl Optimizer {
+
+    pub fn new() -> Optimizer {
+        Optimizer { passes: vec![] }
+    }
+
+    pub fn with_pass<P: Pass + 'static>(mut self, pass: P) -> Optimizer {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    pub fn passes(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.passes.iter().map(|pass| pass.name())
+    }
+
+    pub fn optimize(&self, module: &mut Module) {
+        for function in module.functions.iter_mut() {
+            self.optimize_function(function);
+        }
+    }
+
+    pub fn optimize_function(&self, function: &mut Function) {
+        for _ in 0..MAX_ROUNDS {
+            let mut changed = false;
+
+            for pass in self.passes.iter() {
+                changed |= pass.run(function);
+            }
+
+            if !changed {
+                break;
+            }
+        }
+    }
+}
+
+impl Default for Optimizer {
+    fn default() -> Optimizer {
+        Optimizer::new()
+            .with_pass(ConstantFolding)
+            .with_pass(JumpThreading)
+            .with_pass(DeadCode)
+            .with_pass(RedundantMoves)
+    }
+}
+
+// Tracks the registers holding a known immediate inside each basic block,
+// replaces reads of them with the immediate and arithmetic on them with a
+// `MOVE` of the result, in both the two and three address forms
+pub struct ConstantFolding;
+
+impl Pass for ConstantFolding {
+
+    fn name(&self) -> &'static str {
+        "constant-folding"
+    }
+
+    fn run(&self, function: &mut Function) -> bool {
+        let targets = targets(function);
+
+        let mut known: HashMap<Register, Immediate> = HashMap::new();
+        let mut changed = false;
+
+        function.edit(|instructions| {
+            for (index, instruction) in instructions.iter_mut().enumerate() {
+                if targets.contains(&index) {
+                    known.clear();
+                }
+
+                let opcode = instruction.opcode;
+
+                for (position, operand) in instruction.operands.iter_mut().enumerate() {
+                    if let Operand::Register(register) = operand {
+                        if let (true, Some(value)) = (reads(opcode, position), known.get(register)) {
+                            *operand = Operand::Immediate(*value);
+                            changed = true;
+                        }
+                    }
+                }
+
+                match (opcode, instruction.get(0), instruction.get(1)) {
+                    (OpCode::Move, Operand::Register(register), Operand::Immediate(value)) => {
+                        known.insert(register, value);
+                    }
+                    (_, Operand::Register(register), _) if is_arithmetic(opcode) => {
+                        let sources = match instruction.sources() {
+                            (Operand::Register(lhs), Operand::Immediate(rhs)) => known.get(&lhs).map(|lhs| (*lhs, rhs)),
+                            (Operand::Immediate(lhs), Operand::Immediate(rhs)) => Some((lhs, rhs)),
+                            _ => None,
+                        };
+
+                        let folded = sources.and_then(|(lhs, rhs)| fold(opcode, lhs, rhs));
+
+                        match folded {
+                            Some(value) => {
+                                *instruction = Instruction::new(OpCode::Move, [
+                                    Operand::Register(register),
+                                    Operand::Immediate(value),
+                                    Operand::None,
+                                    Operand::None,
+                                ]);
+                                known.insert(register, value);
+                                changed = true;
+                            }
+                            None => {
+                                known.remove(&register);
+                            }
+                        }
+                    }
+                    _ => {
+                        if let Some(register) = destination(instruction) {
+                            known.remove(&register);
+                        }
+                    }
+                }
+            }
+        });
+
+        changed
+    }
+}
+
+// Jumps to an unconditional `JMP` go straight to its target, and a `JMP`
+// to a `RET` becomes that `RET`
+pub struct JumpThreading;
+
+impl Pass for JumpThreading {
+
+    fn name(&self) -> &'static str {
+        "jump-threading"
+    }
+
+    fn run(&self, function: &mut Function) -> bool {
+        let instructions = function.instructions().to_vec();
+
+        let mut changed = false;
+
+        function.edit(|edited| {
+            for instruction in edited.iter_mut() {
+                if !is_jump(instruction.opcode) {
+                    continue;
+                }
+
+                let mut target = instruction.position(0) as usize;
+                let mut hops = 0;
+
+                while target < instructions.len() && instructions[target].opcode == OpCode::Jmp && hops < instructions.len() {
+                    target = instructions[target].position(0) as usize;
+                    hops += 1;
+                }
+
+                if instruction.opcode == OpCode::Jmp && instructions.get(target).is_some_and(|next| next.opcode == OpCode::Ret) {
+                    *instruction = instructions[target];
+                    changed = true;
+                } else if target != instruction.position(0) as usize {
+                    instruction.operands[0] = Operand::Position(target as u16);
+                    changed = true;
+                }
+            }
+        });
+
+        changed
+    }
+}
+
+// Removes the instructions that can't be reached from the start of the
+// function, and the `JMP`s to the instruction right after them
+pub struct DeadCode;
+
+impl Pass for DeadCode {
+
+    fn name(&self) -> &'static str {
+        "dead-code"
+    }
+
+    fn run(&self, function: &mut Function) -> bool {
+        let instructions = function.instructions();
+
+        let mut reachable = vec![false; instructions.len()];
+        let mut pending = vec![0];
+
+        while let Some(index) = pending.pop() {
+            if index >= instructions.len() || reachable[index] {
+                continue;
+            }
+
+            reachable[index] = true;
+
+            let instruction = instructions[index];
+
+            match instruction.opcode {
+                OpCode::Ret => {}
+                OpCode::Jmp => pending.push(instruction.position(0) as usize),
+                opcode if is_jump(opcode) => {
+                    pending.push(instruction.position(0) as usize);
+                    pending.push(index + 1);
+                }
+                _ => pending.push(index + 1),
+            }
+        }
+
+        let keep = instructions
+            .iter()
+            .enumerate()
+            .map(|(index, instruction)| {
+                let fallthrough = instruction.opcode == OpCode::Jmp && instruction.position(0) as usize == index + 1;
+                reachable[index] && !fallthrough
+            })
+            .collect::<Vec<_>>();
+
+        remove(function, &keep)
+    }
+}
+
+// Removes `MOVE %a, %a`, and a `MOVE` overwritten by the next instruction
+// of the same block
+pub struct RedundantMoves;
+
+impl Pass for RedundantMoves {
+
+    fn name(&self) -> &'static str {
+        "redundant-moves"
+    }
+
+    fn run(&self, function: &mut Function) -> bool {
+        let targets = targets(function);
+        let instructions = function.instructions();
+
+        let keep = instructions
+            .iter()
+            .enumerate()
+            .map(|(index, instruction)| {
+                if instruction.opcode != OpCode::Move {
+                    return true;
+                }
+
+                let register = instruction.get(0);
+
+                if instruction.get(1) == register {
+                    return false;
+                }
+
+                match instructions.get(index + 1) {
+                    Some(next) if !targets.contains(&(index + 1)) => {
+                        !(next.opcode == OpCode::Move && next.get(0) == register && next.get(1) != register)
+                    }
+                    _ => true,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        remove(function, &keep)
+    }
+}
+
+// Drops every instruction not marked in `keep`. Jumps to a removed
+// instruction go to the next one kept, and the source map follows along
+pub fn remove(function: &mut Function, keep: &[bool]) -> bool {
+    if keep.iter().all(|keep| *keep) {
+        return false;
+    }
+
+    let mut positions = Vec::with_capacity(keep.len() + 1);
+    let mut count = 0;
+
+    for keep in keep.iter() {
+        positions.push(count);
+        if *keep {
+            count += 1;
+        }
+    }
+
+    positions.push(count);
+
+    let mut source_map = SourceMap::new(function.source_map.file.clone());
+    let mut instructions = Vec::with_capacity(count);
+
+    for (index, instruction) in function.instructions().iter().enumerate() {
+        if !keep[index] {
+            continue;
+        }
+
+        if let Some(line) = function.source_map.line(index) {
+            source_map.add(instructions.len(), line);
+        }
+
+        let mut instruction = *instruction;
+
+        for operand in instruction.operands.iter_mut() {
+            if let Operand::Position(position) = operand {
+                *position = positions[*position as usize] as u16;
+            }
+        }
+
+        instructions.push(instruction);
+    }
+
+    function.edit(|edited| *edited = instructions);
+    function.source_map = source_map;
+
+    true
+}
+
+fn targets(function: &Function) -> HashSet<usize> {
+    function.instructions()
+        .iter()
+        .filter(|instruction| is_jump(instruction.opcode))
+        .map(|instruction| instruction.position(0) as usize)
+        .collect()
+}
+
+fn is_jump(opcode: OpCode) -> bool {
+    matches!(opcode,
+        OpCode::Jmp
+      | OpCode::Jt
+      | OpCode::Jf
+      | OpCode::JLt
+      | OpCode::JLe
+      | OpCode::JGt
+      | OpCode::JGe
+      | OpCode::JEq
+      | OpCode::JNe
+    )
+}
+
+fn is_comparison(opcode: OpCode) -> bool {
+    matches!(opcode,
+        OpCode::Lt
+      | OpCode::Le
+      | OpCode::Gt
+      | OpCode::Ge
+      | OpCode::Eq
+      | OpCode::Ne
+    )
+}
+
+fn is_arithmetic(opcode: OpCode) -> bool {
+    matches!(opcode,
+        OpCode::Add
+      | OpCode::Sub
+      | OpCode::Mul
+      | OpCode::Div
+      | OpCode::Mod
+      | OpCode::And
+      | OpCode::Or
+      | OpCode::Xor
+      | OpCode::Shl
+      | OpCode::Shr
+    )
+}
+
+// Whether the operand at `position` is only read, so it can be any value
+fn reads(opcode: OpCode, position: usize) -> bool {
+    match opcode {
+        OpCode::Write
+      | OpCode::Print
+      | OpCode::Ret => position == 0,
+        OpCode::Send => position <= 1,
+        OpCode::Get => position >= 1,
+        opcode if is_jump(opcode) => position >= 1,
+        opcode if is_arithmetic(opcode) || is_comparison(opcode) => position >= 1,
+        OpCode::Call
+      | OpCode::Spawn
+      | OpCode::Not
+      | OpCode::Recv
+      | OpCode::Pid
+      | OpCode::Read
+      | OpCode::ReadLn
+      | OpCode::ReadInt
+      | OpCode::ReadNum => false,
+        _ => position == 1,
+    }
+}
+
+// The register an instruction writes to, if any
+fn destination(instruction: &Instruction) -> Option<Register> {
+    let operand = match instruction.opcode {
+        OpCode::Call | OpCode::Spawn => instruction.get(1),
+        OpCode::Write | OpCode::Print | OpCode::Send | OpCode::Ret => Operand::None,
+        opcode if is_jump(opcode) => Operand::None,
+        _ => instruction.get(0),
+    };
+
+    match operand {
+        Operand::Register(register) => Some(register),
+        _ => None,
+    }
+}
+
+// Follows the integer semantics of the interpreter, and gives up whenever
+// the result wouldn't be an immediate or the operation would fail
+fn fold(opcode: OpCode, lhs: Immediate, rhs: Immediate) -> Option<Immediate> {
+    let (lhs, rhs) = (lhs as i64, rhs as i64);
+
+    let value = match opcode {
+        OpCode::Add => lhs + rhs,
+        OpCode::Sub => lhs - rhs,
+        OpCode::Mul => lhs * rhs,
+        OpCode::Div if rhs != 0 => lhs / rhs,
+        OpCode::Mod if rhs != 0 => lhs % rhs,
+        OpCode::And => lhs & rhs,
+        OpCode::Or  => lhs | rhs,
+        OpCode::Xor => lhs ^ rhs,
+        OpCode::Shl if (0..32).contains(&rhs) => lhs << rhs,
+        OpCode::Shr if (0..32).contains(&rhs) => lhs >> rhs,
+        _ => return None,
+    };
+
+    if value >= Immediate::MIN as i64 && value <= Immediate::MAX as i64 {
+        Some(value as Immediate)
+    } else {
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parser::Parser;
+
+    fn optimize<P: Pass + 'static>(pass: P, source: &str) -> Function {
+        let mut module = Parser::new(source).parse().unwrap();
+        Optimizer::new().with_pass(pass).optimize(&mut module);
+        module.functions.remove(0)
+    }
+
+    fn opcodes(function: &Function) -> Vec<OpCode> {
+        function.instructions().iter().map(|instruction| instruction.opcode).collect()
+    }
+
+    #[test]
+    fn fold_constants() {
+        let function = optimize(ConstantFolding, r#"
+            @entrypoint
+              MOVE      %0, 6
+              MUL       %0, 7
+              MOVE      %1, %0
+              ADD       %1, %2
+              DIV       %0, 0
+              SUB       %3, 58, 8
+              SUB       %4, %2, %3
+            .L0
+              ADD       %0, 1
+              RET       %0
+        "#);
+
+        let operands = function.instructions().iter().map(|instruction| instruction.get(1)).collect::<Vec<_>>();
+
+        assert_eq!(opcodes(&function), vec![
+            OpCode::Move, OpCode::Move, OpCode::Move, OpCode::Add, OpCode::Div, OpCode::Move, OpCode::Sub, OpCode::Add, OpCode::Ret,
+        ]);
+        assert_eq!(operands, vec![
+            Operand::Immediate(6),
+            Operand::Immediate(42),
+            Operand::Immediate(42),
+            Operand::Register(2),
+            Operand::Immediate(0),
+            Operand::Immediate(50),
+            Operand::Register(2),
+            Operand::Immediate(1),
+            Operand::None,
+        ]);
+        assert_eq!(function.instructions()[6].get(2), Operand::Immediate(50));
+    }
+
+    #[test]
+    fn thread_jumps() {
+        let function = optimize(JumpThreading, r#"
+            @entrypoint
+              JEQ       .L0, %0, 1
+              JMP       .L1
+            .L0
+              JMP       .L1
+            .L1
+              JMP       .L2
+            .L2
+              RET       %0
+        "#);
+
+        assert_eq!(function.instructions()[0].get(0), Operand::Position(4));
+        assert_eq!(opcodes(&function), vec![OpCode::JEq, OpCode::Ret, OpCode::Ret, OpCode::Ret, OpCode::Ret]);
+    }
+
+    #[test]
+    fn thread_jumps_to_the_end() {
+        let function = optimize(JumpThreading, "@entrypoint\n  JEQ .L0, %0, 1\n  JMP .L1\n.L1\n  JMP .L0\n  RET\n.L0\n");
+
+        assert_eq!(opcodes(&function), vec![OpCode::JEq, OpCode::Jmp, OpCode::Jmp, OpCode::Ret]);
+        assert_eq!(function.instructions()[0].get(0), Operand::Position(4));
+        assert_eq!(function.instructions()[1].get(0), Operand::Position(4));
+    }
+
+    #[test]
+    fn remove_dead_code() {
+        let function = optimize(DeadCode, "@entrypoint\n  JMP .L0\n  WRITE 1\n  WRITE 2\n.L0\n  JLT .L1, %0, 3\n  JMP .L1\n.L1\n  RET %0\n");
+
+        // Once the writes are gone the first jump falls through as well
+        assert_eq!(opcodes(&function), vec![OpCode::JLt, OpCode::Ret]);
+        assert_eq!(function.instructions()[0].get(0), Operand::Position(1));
+        assert_eq!(function.source_map.line(0), Some(6));
+        assert_eq!(function.source_map.line(1), Some(9));
+    }
+
+    #[test]
+    fn remove_redundant_moves() {
+        let function = optimize(RedundantMoves, r#"
+            @entrypoint
+              MOVE      %0, %0
+              MOVE      %1, 1
+              MOVE      %1, 2
+              MOVE      %2, 1
+              MOVE      %2, %2
+            .L0
+              MOVE      %3, 1
+            .L1
+              MOVE      %3, 2
+              JMP       .L1
+        "#);
+
+        assert_eq!(function.instructions().len(), 5);
+        assert_eq!(function.instructions()[0].get(1), Operand::Immediate(2));
+        assert_eq!(function.instructions()[4].get(0), Operand::Position(3));
+    }
+
+    #[test]
+    fn default_pipeline() {
+        let mut module = Parser::new(r#"
+            @entrypoint
+              MOVE      %0, 2
+              ADD       %0, 3
+              JMP       .L0
+              WRITE     %0
+            .L0
+              JMP       .L1
+            .L1
+              RET       %0
+        "#).parse().unwrap();
+
+        let optimizer = Optimizer::default();
+        optimizer.optimize(&mut module);
+
+        assert_eq!(optimizer.passes().collect::<Vec<_>>(), vec![
+            "constant-folding", "jump-threading", "dead-code", "redundant-moves",
+        ]);
+        assert_eq!(module.functions[0].instructions(), vec![
+            Instruction::new(OpCode::Move, [Operand::Register(0), Operand::Immediate(5), Operand::None, Operand::None]),
+            Instruction::new(OpCode::Ret, [Operand::Immediate(5), Operand::None, Operand::None, Operand::None]),
+        ]);
+    }
+}
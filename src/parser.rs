@@ -1,18 +1,26 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    convert::TryFrom,
+};
 
 use crate::{
     bytecode::{
+        accepts,
         OpCode,
         Module,
         Function,
         Constant,
         Operand,
+        Immediate,
         Instruction,
         Register,
+        SourceMap,
     },
     error:: {
+        Diagnostics,
         Result,
         MachinaError,
+        Span,
     },
     lexer::{
         Lexer,
@@ -20,10 +28,31 @@ use crate::{
     }
 };
 
+// How deep macros may expand inside each other before they count as recursive
+const MAX_DEPTH: usize = 256;
+
 #[derive(Debug, Clone)]
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
     token: Token,
+    value: Option<String>,
+    span: Span,
+    origin: Option<Origin>,
+    // Expanded macros are pushed to the front of `pending`, so the rest of
+    // the parser reads them as if they were written at the call site
+    pending: VecDeque<Lexeme>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    defines: HashMap<String, Literal>,
+    scoped: HashMap<String, Literal>,
+    aliases: HashMap<String, Register>,
+    data: HashMap<String, Constant>,
+    interned: HashMap<Constant, usize>,
+    arities: Vec<Option<u8>>,
+    file: String,
+    imports: Vec<String>,
+    externs: Vec<String>,
+    diagnostics: Diagnostics,
 }
 
 impl<'a> Parser<'a> {
@@ -31,38 +60,98 @@ impl<'a> Parser<'a> {
         let mut parser = Parser {
             lexer: Lexer::new(source),
             token: Token::EOF,
+            value: None,
+            span: Span::default(),
+            origin: None,
+            pending: VecDeque::new(),
+            macros: HashMap::new(),
+            expansions: 0,
+            defines: HashMap::new(),
+            scoped: HashMap::new(),
+            aliases: HashMap::new(),
+            data: HashMap::new(),
+            interned: HashMap::new(),
+            arities: vec![],
+            file: "<input>".into(),
+            imports: vec![],
+            externs: vec![],
+            diagnostics: Diagnostics::new(),
         };
 
         parser.initilize();
         parser
     }
 
-    fn initilize(&mut self) {
-        let _ = self.next();
+    pub fn with_file(mut self, file: &str) -> Parser<'a> {
+        self.file = file.into();
+        self
+    }
 
-        while self.token_is(Token::EOL) {
-            let _ = self.next();
+    fn initilize(&mut self) {
+        if let Err(error) = self.next() {
+            self.recover(error);
         }
+
+        self.next_line();
     }
 
-    pub fn parse(mut self) -> Result<Module> {
+    pub fn parse(mut self) -> std::result::Result<Module, Diagnostics> {
         let mut functions = vec![];
 
         while !self.token_is(Token::EOF) {
-            functions.push(self.parse_function()?);
+            if matches!(self.token, Token::Import | Token::Extern | Token::Macro | Token::Define | Token::Equ | Token::Data) {
+                let directive = match self.token {
+                    Token::Import => self.parse_import(),
+                    Token::Extern => self.parse_extern(),
+                    Token::Macro => self.parse_macro(),
+                    Token::Data => self.parse_data(),
+                    _ => self.parse_define(false),
+                };
+
+                if let Err(error) = directive {
+                    self.recover(error);
+                }
+                self.next_line();
+                continue;
+            }
+
+            match self.parse_function() {
+                Ok(function) => {
+                    functions.push(function);
+                }
+                Err(error) => {
+                    self.recover(error);
+                    self.next_line();
+
+                    while !self.token_is(Token::Function)
+                      &&  !self.token_is(Token::Import)
+                      &&  !self.token_is(Token::Extern)
+                      &&  !self.token_is(Token::Macro)
+                      &&  !self.token_is(Token::Define)
+                      &&  !self.token_is(Token::Equ)
+                      &&  !self.token_is(Token::Data)
+                      &&  !self.token_is(Token::EOF) {
+                        self.skip_line();
+                    }
+                }
+            }
         }
 
         self.build(functions)
     }
 
-    pub fn build(mut self, functions: Vec<PreFunction>) -> Result<Module> {
+    pub fn build(mut self, functions: Vec<PreFunction>) -> std::result::Result<Module, Diagnostics> {
 
-        let indexes = functions.iter()
-            .enumerate()
-            .map(|(idx, function)| {
-                (function.name.clone(), idx)
-            })
-            .collect::<HashMap<_,_>>();
+        let mut indexes = HashMap::new();
+
+        for (idx, function) in functions.iter().enumerate() {
+            if indexes.insert(function.name.clone(), idx).is_some() {
+                let error = MachinaError::DuplicateFunction(function.name.clone(), self.file.clone());
+                let _ = self.diagnostics.report::<()>(error);
+            }
+        }
+
+        self.arities = functions.iter().map(|function| function.arity).collect();
 
         let mut constants = vec![];
 
@@ -71,9 +160,22 @@ impl<'a> Parser<'a> {
             .map(|function| {
                 self.build_function(function, &indexes, &mut constants)
             })
-            .collect::<Result<Vec<_>>>()?;
+            .collect::<Vec<_>>();
 
-        Ok(Module { functions, constants })
+        if !self.diagnostics.empty() {
+            return Err(self.diagnostics);
+        }
+
+        let functions = functions.into_iter().flatten().collect();
+
+        Ok(Module {
+            file: self.file,
+            functions,
+            constants,
+            symbols: indexes,
+            imports: self.imports,
+            externs: self.externs,
+        })
     }
 
     fn build_function(&mut self, function: PreFunction, functions: &HashMap<String, usize>, constants: &mut Vec<Constant>)
@@ -89,45 +191,105 @@ impl<'a> Parser<'a> {
 
         let mut registers = HashSet::new();
 
+        let mut source_map = SourceMap::new(self.file.clone());
+
+        // Expanded instructions take the line of the macro call
+        for (index, instruction) in function.blocks.iter().flat_map(|b| b.instructions.iter()).enumerate() {
+            let span = instruction.origin.as_ref().map_or(instruction.span, |origin| origin.span);
+            source_map.add(index, span.line);
+        }
+
+        let aliases = function.aliases;
+
         let instructions = function.blocks
             .into_iter()
             .map(|b| b.instructions)
             .flatten()
             .map(|instruction| {
-                self.build_instruction(instruction, &labels, &mut registers, functions, constants)
+                self.build_instruction(instruction, &labels, &aliases, &mut registers, functions, constants)
             })
-            .collect::<Result<Vec<_>>>()?;
+            .collect::<Vec<_>>(); // builds every instruction, so all errors are reported
+
+        let instructions = instructions.into_iter().collect::<Result<Vec<_>>>()?;
 
-        Ok(Function { locals: registers.len() as u8, instructions })
+        let used = registers.iter().max().map_or(0, |register| *register as usize + 1);
+
+        let locals = match function.locals {
+            Some(locals) if (locals as usize) < used || Some(locals) < function.arity => {
+                let used = used.max(function.arity.unwrap_or(0) as usize);
+                return self.diagnostics.report_with_span(MachinaError::TooFewLocals(function.name, locals, used), function.span);
+            }
+            Some(locals) => locals,
+            // Registers, aliased or not, are checked to be below `u8::MAX`
+            None => u8::try_from(used.max(function.arity.unwrap_or(0) as usize)).expect("too many locals"),
+        };
+
+        Ok(Function::new(function.name, locals, instructions)
+            .with_arity(function.arity)
+            .with_source_map(source_map))
     }
 
-    fn build_instruction(&mut self, function: PreInstruction, labels: &HashMap<String, usize>, registers: &mut HashSet<Register>, functions: &HashMap<String, usize>, constants: &mut Vec<Constant>)
+    fn build_instruction(&mut self, function: PreInstruction, labels: &HashMap<String, usize>, aliases: &HashMap<String, Register>, registers: &mut HashSet<Register>, functions: &HashMap<String, usize>, constants: &mut Vec<Constant>)
         -> Result<Instruction>
     {
         let mut operands = [Operand::None; 4];
 
-        for (i, operand) in function.operands.into_iter().enumerate() {
+        let notes = Origin::notes(&function.origin);
+
+        for (i, (operand, span)) in function.operands.into_iter().enumerate() {
             operands[i] = match operand {
 
                 PreOperand::String(string) => {
                     self.define_constant(Constant::String(string), constants)
                 }
 
-                PreOperand::Number(number) => {
-                    let num = number.parse::<f64>().unwrap();
-
-                    if num <= f32::MAX as f64 && (num.trunc() == num) {
-                        Operand::Immediate((num as f32) as i32)
-                    } else {
-                        self.define_constant(Constant::Number(num.into()), constants)
+                PreOperand::Integer(value) => {
+                    match Immediate::try_from(value) {
+                        Ok(value) => Operand::Immediate(value),
+                        Err(_) => self.define_constant(Constant::Integer(value), constants),
                     }
                 }
 
+                PreOperand::Float(value) => {
+                    self.define_constant(Constant::Number(value.into()), constants)
+                }
+
+                PreOperand::Char(value) => {
+                    self.define_constant(Constant::Char(value), constants)
+                }
+
+                PreOperand::Boolean(value) => {
+                    self.define_constant(Constant::Boolean(value), constants)
+                }
+
+                PreOperand::Null => {
+                    self.define_constant(Constant::Null, constants)
+                }
+
+                // Each entry is added to the pool the first time it is used
+                PreOperand::Data(name) => {
+                    let constant = self.data[&name].clone();
+                    self.define_constant(constant, constants)
+                }
+
                 PreOperand::Register(register) => {
-                    let register = register.parse::<u16>().ok()
-                        .ok_or(
-                            MachinaError::InvalidRegister(register)
-                        )?;
+                    let register = match (register.parse::<u16>(), aliases.get(&register)) {
+                        (Ok(index), _) if index >= u8::MAX as u16 => {
+                            return self.diagnostics.report_with_notes(MachinaError::InvalidRegister(register), span, notes);
+                        }
+                        (Ok(register), _) => register,
+                        // Already reported where the alias is defined
+                        (Err(_), Some(alias)) if *alias >= u8::MAX as u16 => {
+                            return Err(MachinaError::InvalidRegister(register));
+                        }
+                        (Err(_), Some(alias)) => *alias,
+                        (Err(_), None) if register.starts_with(|c: char| c.is_ascii_digit()) => {
+                            return self.diagnostics.report_with_notes(MachinaError::InvalidRegister(register), span, notes);
+                        }
+                        (Err(_), None) => {
+                            return self.diagnostics.report_with_notes(MachinaError::RegisterAliasNotFound(register), span, notes);
+                        }
+                    };
 
                     registers.insert(register);
 
@@ -135,19 +297,24 @@ impl<'a> Parser<'a> {
                 }
 
                 PreOperand::Function(name) => {
-                    let function = functions.get(&name)
-                        .ok_or({
-                            MachinaError::FunctionNotFound(name)
-                        })?;
-
-                    Operand::Function(*function as u16)
+                    let function = match (functions.get(&name), self.externs.iter().position(|function| *function == name)) {
+                        (Some(function), _) => *function,
+                        (None, Some(index)) => functions.len() + index,
+                        (None, None) => {
+                            return self.diagnostics.report_with_notes(MachinaError::FunctionNotFound(name), span, notes);
+                        }
+                    };
+
+                    Operand::Function(function as u16)
                 }
 
                 PreOperand::Label(label) => {
-                    let position = labels.get(&label)
-                        .ok_or(
-                            MachinaError::TargetNotFound(label)
-                        )?;
+                    let position = match labels.get(&label) {
+                        Some(position) => position,
+                        None => {
+                            return self.diagnostics.report_with_notes(MachinaError::TargetNotFound(label), span, notes);
+                        }
+                    };
 
                     Operand::Position(*position as u16)
                 }
@@ -156,50 +323,551 @@ impl<'a> Parser<'a> {
             };
         }
 
+        if let (OpCode::Call | OpCode::Spawn, Operand::Function(index), Operand::Register(first), Operand::Register(last))
+            = (function.opcode, operands[0], operands[2], operands[3])
+        {
+            let count = (last as usize + 1).saturating_sub(first as usize);
+
+            if let Some(Some(arity)) = self.arities.get(index as usize) {
+                if !accepts(*arity, count) {
+                    let name = functions.iter().find(|(_, i)| **i == index as usize).unwrap().0.clone();
+                    return self.diagnostics.report_with_notes(MachinaError::ArityMismatch(name, *arity, count), function.span, notes);
+                }
+            }
+        }
+
         Ok(Instruction { opcode: function.opcode, operands })
     }
 
-    fn define_constant(&self, constant: Constant, constants: &mut Vec<Constant>) -> Operand {
-        constants.push(constant);
-        Operand::Constant((constants.len() - 1) as u16)
+    // Equal constants share one entry of the pool
+    fn define_constant(&mut self, constant: Constant, constants: &mut Vec<Constant>) -> Operand {
+        let index = *self.interned.entry(constant).or_insert_with_key(|constant| {
+            constants.push(constant.clone());
+            constants.len() - 1
+        });
+
+        Operand::constant(index)
+    }
+
+    fn parse_import(&mut self) -> Result<()> {
+        self.eat(Token::Import)?;
+
+        let path = self.take(Token::String)?;
+        self.imports.push(path);
+
+        self.expect_one_of(&[Token::EOL, Token::EOF])
+    }
+
+    // A function an imported module defines, which the linker resolves
+    fn parse_extern(&mut self) -> Result<()> {
+        self.eat(Token::Extern)?;
+
+        let name = self.take(Token::Function)?;
+
+        if !self.externs.contains(&name) {
+            self.externs.push(name);
+        }
+
+        self.expect_one_of(&[Token::EOL, Token::EOF])
+    }
+
+    fn parse_macro(&mut self) -> Result<()> {
+        let span = self.span;
+
+        self.eat(Token::Macro)?;
+
+        let name = self.take(Token::Identifier)?;
+
+        let mut params = vec![];
+
+        self.eat(Token::LParen)?;
+
+        while !self.token_is(Token::RParen) {
+            params.push(self.take(Token::Identifier)?);
+
+            if !self.token_is(Token::RParen) {
+                self.eat(Token::Comma)?;
+            }
+        }
+
+        self.eat(Token::RParen)?;
+        self.expect_one_of(&[Token::EOL])?;
+
+        let mut body = vec![];
+
+        loop {
+            if let Err(error) = self.next() {
+                self.report(error, self.span);
+                continue;
+            }
+
+            match self.token {
+                Token::EndMacro => break,
+                Token::EOF => {
+                    self.report(MachinaError::MacroNotTerminated(name), span);
+                    return Ok(());
+                }
+                _ => body.push(self.lexeme()),
+            }
+        }
+
+        self.next()?;
+
+        match self.macros.entry(name) {
+            Entry::Occupied(entry) => {
+                let error = MachinaError::MacroAlreadyDefined(entry.key().clone());
+                self.report(error, span);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(Macro { params, body });
+            }
+        }
+
+        self.expect_one_of(&[Token::EOL, Token::EOF])
+    }
+
+    fn expand_macro(&mut self) -> Result<()> {
+        let span = self.span;
+        let depth = self.origin.as_ref().map_or(0, |origin| origin.depth) + 1;
+
+        let name = self.value.clone().unwrap_or_default();
+
+        let definition = match self.macros.get(&name) {
+            Some(definition) => definition.clone(),
+            None => {
+                return Err(MachinaError::InvalidInstruction(name));
+            }
+        };
+
+        self.next()?;
+
+        let mut args = vec![];
+
+        while !self.token_is(Token::EOL) && !self.token_is(Token::EOF) {
+            self.expect_one_of(&[
+                Token::String,
+                Token::Number,
+                Token::Char,
+                Token::True,
+                Token::False,
+                Token::Null,
+                Token::Register,
+                Token::Label,
+                Token::Function,
+                Token::Identifier,
+            ])?;
+
+            args.push(self.lexeme());
+            self.next()?;
+
+            if !self.token_is(Token::EOL) && !self.token_is(Token::EOF) {
+                self.eat(Token::Comma)?;
+            }
+        }
+
+        if args.len() != definition.params.len() {
+            self.report(MachinaError::MacroArguments(name, definition.params.len(), args.len()), span);
+            return Ok(());
+        }
+
+        // The rest of the expansion it came from is dropped too, or a macro
+        // that expands to itself twice would be reported forever
+        if depth > MAX_DEPTH {
+            self.report(MachinaError::MacroRecursion(name), span);
+            self.pending.clear();
+            return Ok(());
+        }
+
+        self.expansions += 1;
+
+        // Labels defined by the body get a suffix that can't be written by
+        // hand, so expanding the same macro twice doesn't clash
+        let locals = definition.body
+            .iter()
+            .enumerate()
+            .filter(|(index, lexeme)| {
+                lexeme.token == Token::Label && (*index == 0 || definition.body[index - 1].token == Token::EOL)
+            })
+            .filter_map(|(_, lexeme)| lexeme.value.clone())
+            .collect::<HashSet<_>>();
+
+        let origin = Origin { name, span, depth };
+
+        let Macro { params, body } = definition;
+
+        let expansion = body
+            .into_iter()
+            .map(|lexeme| {
+                let mut lexeme = match (&lexeme.token, &lexeme.value) {
+                    (Token::Identifier, Some(value)) => {
+                        match params.iter().position(|param| param == value) {
+                            Some(index) => args[index].clone(),
+                            None => lexeme,
+                        }
+                    }
+                    (Token::Label, Some(value)) if locals.contains(value) => {
+                        Lexeme { value: Some(format!("{}.{}", value, self.expansions)), ..lexeme }
+                    }
+                    _ => lexeme,
+                };
+
+                lexeme.origin = Some(origin.clone());
+                lexeme
+            })
+            .collect::<Vec<_>>();
+
+        for lexeme in expansion.into_iter().rev() {
+            self.pending.push_front(lexeme);
+        }
+
+        Ok(())
+    }
+
+    // Constants defined before the first function are seen by the whole
+    // file, the ones defined inside a function only by the rest of it
+    fn parse_define(&mut self, scoped: bool) -> Result<()> {
+        let span = self.span;
+
+        let separator = if self.token_is(Token::Equ) { Token::Comma } else { Token::Equal };
+
+        self.next()?;
+
+        let name = self.take(Token::Identifier)?;
+
+        self.eat(separator)?;
+
+        let value = self.parse_expression()?;
+
+        self.expect_one_of(&[Token::EOL, Token::EOF])?;
+
+        let defines = if scoped { &mut self.scoped } else { &mut self.defines };
+
+        let defined = match defines.entry(name) {
+            Entry::Occupied(entry) => Some(entry.key().clone()),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+                None
+            }
+        };
+
+        if let Some(name) = defined {
+            self.report(MachinaError::ConstantAlreadyDefined(name), span);
+        }
+
+        Ok(())
+    }
+
+    // Aliases are per function, and can be used anywhere inside it
+    fn parse_alias(&mut self) -> Result<()> {
+        let span = self.span;
+
+        self.eat(Token::Reg)?;
+
+        let name = self.take(Token::Identifier)?;
+
+        self.eat(Token::Equal)?;
+
+        let register = self.take(Token::Register)?;
+
+        let index = match register.parse::<Register>() {
+            Ok(index) => index,
+            Err(_) => return Err(MachinaError::InvalidRegister(register)),
+        };
+
+        self.expect_one_of(&[Token::EOL, Token::EOF])?;
+
+        if index >= u8::MAX as u16 {
+            self.report(MachinaError::InvalidRegister(register), span);
+        }
+
+        let register = index;
+
+        match self.aliases.get(&name) {
+            Some(bound) if *bound != register => {
+                let error = MachinaError::RegisterAliasConflict(name, *bound);
+                self.report(error, span);
+            }
+            _ => {
+                self.aliases.insert(name, register);
+            }
+        }
+
+        Ok(())
+    }
+
+    // `.data` is followed by one `name = [values]` or `name = bytes ...`
+    // per line, and ends at the first line that isn't one
+    fn parse_data(&mut self) -> Result<()> {
+        self.eat(Token::Data)?;
+        self.expect_one_of(&[Token::EOL, Token::EOF])?;
+        self.next_line();
+
+        while self.token_is(Token::Identifier) {
+            if let Err(error) = self.parse_data_entry() {
+                self.recover(error);
+            }
+            self.next_line();
+        }
+
+        Ok(())
+    }
+
+    fn parse_data_entry(&mut self) -> Result<()> {
+        let span = self.span;
+
+        let name = self.take(Token::Identifier)?;
+
+        self.eat(Token::Equal)?;
+
+        let value = if self.token_is(Token::Identifier) && self.value.as_deref() == Some("bytes") {
+            self.next()?;
+            self.parse_bytes()?
+        } else {
+            self.parse_list()?
+        };
+
+        self.expect_one_of(&[Token::EOL, Token::EOF])?;
+
+        if self.data.contains_key(&name) || self.defines.contains_key(&name) {
+            self.report(MachinaError::ConstantAlreadyDefined(name), span);
+        } else {
+            self.data.insert(name, value);
+        }
+
+        Ok(())
+    }
+
+    fn parse_list(&mut self) -> Result<Constant> {
+        let mut values = vec![];
+
+        self.eat(Token::LBracket)?;
+
+        while !self.token_is(Token::RBracket) {
+            let value = if self.token_is(Token::LBracket) {
+                self.parse_list()?
+            } else {
+                match self.parse_expression()? {
+                    Literal::Integer(value) => Constant::Integer(value),
+                    Literal::Float(value) => Constant::Number(value.into()),
+                    Literal::String(value) => Constant::String(value),
+                    Literal::Char(value) => Constant::Char(value),
+                    Literal::Boolean(value) => Constant::Boolean(value),
+                    Literal::Null => Constant::Null,
+                    Literal::Data(name) => self.data[&name].clone(),
+                }
+            };
+
+            values.push(value);
+
+            if !self.token_is(Token::RBracket) {
+                self.eat(Token::Comma)?;
+            }
+        }
+
+        self.eat(Token::RBracket)?;
+
+        Ok(Constant::List(values))
     }
 
+    // `bytes "text"`, `bytes [1, 2, 3]` or `bytes 16` for a zeroed buffer
+    fn parse_bytes(&mut self) -> Result<Constant> {
+        if !self.token_is(Token::LBracket) {
+            return match self.parse_expression()? {
+                Literal::String(value) => Ok(Constant::Bytes(value.into_bytes())),
+                Literal::Integer(size) if size >= 0 => Ok(Constant::Bytes(vec![0; size as usize])),
+                _ => Err(MachinaError::InvalidExpression("expected a string or a size for `bytes`".into())),
+            };
+        }
+
+        let mut bytes = vec![];
+
+        self.eat(Token::LBracket)?;
+
+        while !self.token_is(Token::RBracket) {
+            match self.parse_expression()? {
+                Literal::Integer(byte) if (0..=u8::MAX as i64).contains(&byte) => bytes.push(byte as u8),
+                _ => return Err(MachinaError::InvalidExpression("bytes must be integers between 0 and 255".into())),
+            }
+
+            if !self.token_is(Token::RBracket) {
+                self.eat(Token::Comma)?;
+            }
+        }
+
+        self.eat(Token::RBracket)?;
+
+        Ok(Constant::Bytes(bytes))
+    }
+
+    fn parse_expression(&mut self) -> Result<Literal> {
+        let mut lhs = self.parse_term()?;
+
+        loop {
+            match self.token {
+                Token::Plus | Token::Minus => {
+                    let operator = self.token;
+                    self.next()?;
+                    lhs = lhs.apply(operator, self.parse_term()?)?;
+                }
+                // `N+1` is lexed as `N` and the number `+1`
+                Token::Number if self.value.as_ref().is_some_and(|v| v.starts_with(&['+', '-'][..])) => {
+                    lhs = lhs.apply(Token::Plus, self.parse_term()?)?;
+                }
+                _ => return Ok(lhs),
+            }
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<Literal> {
+        let mut lhs = self.parse_unary()?;
+
+        while matches!(self.token, Token::Star | Token::Slash) {
+            let operator = self.token;
+            self.next()?;
+            lhs = lhs.apply(operator, self.parse_unary()?)?;
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Literal> {
+        if self.token_is(Token::Minus) {
+            self.next()?;
+            return Literal::Integer(0).apply(Token::Minus, self.parse_unary()?);
+        }
+
+        match self.token {
+            Token::Number => {
+                let number = self.take(Token::Number)?;
+
+                if number.contains('.') {
+                    Ok(Literal::Float(number.parse().unwrap()))
+                } else {
+                    number
+                        .parse()
+                        .map(Literal::Integer)
+                        .map_err(|_| MachinaError::InvalidExpression(format!("`{}` is too large", number)))
+                }
+            }
+            Token::String => {
+                Ok(Literal::String(self.take(Token::String)?))
+            }
+            Token::Char => {
+                Ok(Literal::Char(self.take(Token::Char)?.chars().next().unwrap()))
+            }
+            Token::True | Token::False => {
+                let value = self.token_is(Token::True);
+                self.next()?;
+                Ok(Literal::Boolean(value))
+            }
+            Token::Null => {
+                self.next()?;
+                Ok(Literal::Null)
+            }
+            Token::Identifier => {
+                let name = self.take(Token::Identifier)?;
+
+                match self.scoped.get(&name).or_else(|| self.defines.get(&name)) {
+                    Some(value) => Ok(value.clone()),
+                    None if self.data.contains_key(&name) => Ok(Literal::Data(name)),
+                    None => Err(MachinaError::ConstantNotFound(name)),
+                }
+            }
+            Token::LParen => {
+                self.next()?;
+                let value = self.parse_expression()?;
+                self.eat(Token::RParen)?;
+                Ok(value)
+            }
+            _ => {
+                Err(self.unexpected(&[Token::Number, Token::String, Token::Identifier, Token::LParen]))
+            }
+        }
+    }
+
+    // `@name`, optionally followed by the number of parameters and the
+    // number of registers, as in `@fib(1) locals 3`
     fn parse_function(&mut self) -> Result<PreFunction> {
+        let span = self.span;
 
         let name = self.take(Token::Function)?;
 
-        self.next_line()?;
+        self.scoped.clear();
+        self.aliases.clear();
+
+        let mut arity = None;
+        let mut locals = None;
+
+        if self.token_is(Token::LParen) {
+            self.next()?;
+            arity = Some(if self.token_is(Token::RParen) { 0 } else { self.parse_count()? });
+            self.eat(Token::RParen)?;
+        }
+
+        if self.token_is(Token::Identifier) && self.value.as_deref() == Some("locals") {
+            self.next()?;
+            locals = Some(self.parse_count()?);
+        }
+
+        self.expect_one_of(&[Token::EOL, Token::EOF])?;
+
+        self.next_line();
 
         let mut blocks = vec![];
 
-        blocks.push(self.parse_block("<main>".into())?);
+        blocks.push(self.parse_block("<main>".into()));
 
         while self.token_is(Token::Label) {
             let label = self.take(Token::Label)?;
-            self.next_line()?;
-            let block = self.parse_block(label)?;
+            self.next_line();
+            let block = self.parse_block(label);
             blocks.push(block);
         }
 
-        Ok(PreFunction { name, blocks })
+        let aliases = std::mem::take(&mut self.aliases);
+
+        Ok(PreFunction { name, span, arity, locals, blocks, aliases })
     }
 
-    fn parse_block(&mut self, label: String) -> Result<Block> {
+    fn parse_count(&mut self) -> Result<u8> {
+        match self.parse_expression()? {
+            Literal::Integer(count) if (0..=u8::MAX as i64).contains(&count) => Ok(count as u8),
+            Literal::Integer(count) => Err(MachinaError::InvalidCount(count.to_string())),
+            Literal::Float(count) => Err(MachinaError::InvalidCount(count.to_string())),
+            Literal::String(count) | Literal::Data(count) => Err(MachinaError::InvalidCount(count)),
+            Literal::Char(count) => Err(MachinaError::InvalidCount(format!("'{}'", count))),
+            Literal::Boolean(count) => Err(MachinaError::InvalidCount(count.to_string())),
+            Literal::Null => Err(MachinaError::InvalidCount("null".into())),
+        }
+    }
+
+    fn parse_block(&mut self, label: String) -> Block {
         let mut instructions = vec![];
 
         while !self.token_is(Token::Label)
           &&  !self.token_is(Token::Function)
           &&  !self.token_is(Token::EOF) {
-            instructions.push(self.parse_instruction()?);
-            self.next_line()?;
+            let result = match self.token {
+                Token::Macro => self.parse_macro(),
+                Token::Define | Token::Equ => self.parse_define(true),
+                Token::Reg => self.parse_alias(),
+                Token::Identifier => self.expand_macro(),
+                _ => self.parse_instruction().map(|instruction| instructions.push(instruction)),
+            };
+
+            if let Err(error) = result {
+                self.recover(error);
+            }
+            self.next_line();
         }
 
-        Ok(Block { label, instructions })
+        Block { label, instructions }
     }
 
     fn parse_instruction(&mut self) -> Result<PreInstruction> {
         match self.token {
-            Token::Call => self.parse_call_instruction(),
+            Token::Call
+          | Token::Spawn => self.parse_call_instruction(),
             Token::Move => self.parse_move_instruction(),
 
             Token::Jmp
@@ -227,11 +895,21 @@ impl<'a> Parser<'a> {
           | Token::Or
           | Token::Xor
           | Token::Shl
-          | Token::Shr => self.parse_binary_instructions(),
+          | Token::Shr
+          | Token::Send
+          | Token::Len
+          | Token::Get => self.parse_binary_instructions(),
 
             Token::Ret
           | Token::Not
-          | Token::Write => self.parse_unary_instructions(),
+          | Token::Write
+          | Token::Print
+          | Token::Read
+          | Token::ReadLn
+          | Token::ReadInt
+          | Token::ReadNum
+          | Token::Recv
+          | Token::Pid => self.parse_unary_instructions(),
 
             _ => {
                 return Err(self.unexpected(&[Token::Instruction]));
@@ -240,7 +918,17 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_call_instruction(&mut self) -> Result<PreInstruction> {
-        self.eat(Token::Call)?;
+        let (span, origin) = (self.span, self.origin.clone());
+
+        let opcode = match self.token {
+            Token::Call => OpCode::Call,
+            Token::Spawn => OpCode::Spawn,
+            _ => {
+                return Err(self.unexpected(&[Token::Instruction]));
+            }
+        };
+
+        self.next()?;
 
         let operands = vec![
             self.parse_operand(Token::Function, false, true)?,
@@ -249,12 +937,12 @@ impl<'a> Parser<'a> {
             self.parse_operand(Token::Register, false, false)?,
         ];
 
-        let line = self.line();
-
-        Ok(PreInstruction { opcode: OpCode::Call, line, operands })
+        Ok(PreInstruction { opcode, span, origin, operands })
     }
 
     fn parse_move_instruction(&mut self) -> Result<PreInstruction> {
+        let (span, origin) = (self.span, self.origin.clone());
+
         self.eat(Token::Move)?;
 
         let operands = vec![
@@ -262,12 +950,12 @@ impl<'a> Parser<'a> {
             self.parse_operand(Token::Operand, false, false)?,
         ];
 
-        let line = self.line();
-
-        Ok(PreInstruction { opcode: OpCode::Move, line, operands })
+        Ok(PreInstruction { opcode: OpCode::Move, span, origin, operands })
     }
 
     fn parse_jump_instructions(&mut self) -> Result<PreInstruction> {
+        let (span, origin) = (self.span, self.origin.clone());
+
         let opcode = match self.token {
             Token::Jmp => OpCode::Jmp,
             Token::Jt  => OpCode::Jt,
@@ -309,16 +997,23 @@ impl<'a> Parser<'a> {
             _ => unreachable!()
         };
 
-        let line = self.line();
-
-        Ok(PreInstruction { opcode, line, operands })
+        Ok(PreInstruction { opcode, span, origin, operands })
     }
 
     fn parse_unary_instructions(&mut self) -> Result<PreInstruction> {
+        let (span, origin) = (self.span, self.origin.clone());
+
         let opcode = match self.token {
             Token::Not => OpCode::Not,
             Token::Ret => OpCode::Ret,
             Token::Write => OpCode::Write,
+            Token::Print => OpCode::Print,
+            Token::Read => OpCode::Read,
+            Token::ReadLn => OpCode::ReadLn,
+            Token::ReadInt => OpCode::ReadInt,
+            Token::ReadNum => OpCode::ReadNum,
+            Token::Recv => OpCode::Recv,
+            Token::Pid => OpCode::Pid,
             _ => {
                 return Err(self.unexpected(&[Token::Instruction]));
             }
@@ -326,16 +1021,22 @@ impl<'a> Parser<'a> {
 
         self.next()?;
 
+        let kind = if matches!(opcode, OpCode::Write | OpCode::Print) {
+            Token::Operand
+        } else {
+            Token::Register
+        };
+
         let operands = vec![
-            self.parse_operand(Token::Register, matches!(opcode, OpCode::Ret | OpCode::Write), false)?
+            self.parse_operand(kind, matches!(opcode, OpCode::Ret | OpCode::Write), false)?
         ];
 
-        let line = self.line();
-
-        Ok(PreInstruction { opcode, line, operands })
+        Ok(PreInstruction { opcode, span, origin, operands })
     }
 
     fn parse_binary_instructions(&mut self) -> Result<PreInstruction> {
+        let (span, origin) = (self.span, self.origin.clone());
+
         let opcode = match self.token {
             Token::Lt => OpCode::Lt,
             Token::Le => OpCode::Le,
@@ -353,6 +1054,9 @@ impl<'a> Parser<'a> {
             Token::Xor => OpCode::Xor,
             Token::Shl => OpCode::Shl,
             Token::Shr => OpCode::Shr,
+            Token::Send => OpCode::Send,
+            Token::Len => OpCode::Len,
+            Token::Get => OpCode::Get,
             _ => {
                 return Err(self.unexpected(&[Token::Instruction]));
             }
@@ -360,31 +1064,61 @@ impl<'a> Parser<'a> {
 
         self.next()?;
 
-        let operands = vec![
+        let mut operands = vec![
             self.parse_operand(Token::Register, false, true)?,
             self.parse_operand(Token::Operand, false, false)?,
         ];
 
-        let line = self.line();
+        // `ADD %dst, a, b` stores `a + b`, while `ADD %dst, b` adds to `%dst`
+        let three_address = !matches!(opcode, OpCode::Send | OpCode::Len | OpCode::Get);
+
+        if opcode == OpCode::Get || (three_address && self.token_is(Token::Comma)) {
+            self.eat(Token::Comma)?;
+            operands.push(self.parse_operand(Token::Operand, false, false)?);
+        }
 
-        Ok(PreInstruction { opcode, line, operands })
+        Ok(PreInstruction { opcode, span, origin, operands })
     }
 
-    fn parse_operand(&mut self, kind: Token, optional: bool, eat_comma: bool) -> Result<PreOperand> {
+    fn parse_operand(&mut self, kind: Token, optional: bool, eat_comma: bool) -> Result<(PreOperand, Span)> {
 
         if optional && matches!(self.token, Token::EOF | Token::EOL) {
-            return Ok(PreOperand::None);
+            return Ok((PreOperand::None, self.span));
         }
 
+        let span = self.span;
+
         if kind == Token::Operand {
-            self.expect_one_of(&[Token::String, Token::Number, Token::Register])?;
+            self.expect_one_of(&[
+                Token::String,
+                Token::Number,
+                Token::Char,
+                Token::True,
+                Token::False,
+                Token::Null,
+                Token::Register,
+                Token::Identifier,
+                Token::LParen,
+                Token::Minus,
+            ])?;
         } else {
             self.expect_one_of(&[kind])?;
         }
 
         let operand = match self.token {
+            Token::String
+          | Token::Number
+          | Token::Char
+          | Token::True
+          | Token::False
+          | Token::Null
+          | Token::Identifier
+          | Token::LParen
+          | Token::Minus if kind == Token::Operand => {
+                self.parse_expression()?.into_operand()
+            }
             Token::String => PreOperand::String(self.take(Token::String)?),
-            Token::Number => PreOperand::Number(self.take(Token::Number)?),
+            Token::Number => self.parse_unary()?.into_operand(),
             Token::Register => PreOperand::Register(self.take(Token::Register)?),
             Token::Function => PreOperand::Function(self.take(Token::Function)?),
             Token::Label => PreOperand::Label(self.take(Token::Label)?),
@@ -399,23 +1133,72 @@ impl<'a> Parser<'a> {
             self.eat(Token::Comma)?;
         }
 
-        Ok(operand)
+        Ok((operand, span))
     }
 
     fn next(&mut self) -> Result<()> {
-        self.token = if let Some(token) = self.lexer.next() {
-            token?
-        } else {
-            Token::EOF
-        };
-        Ok(())
+        if let Some(lexeme) = self.pending.pop_front() {
+            self.token = lexeme.token;
+            self.value = lexeme.value;
+            self.span = lexeme.span;
+            self.origin = lexeme.origin;
+            return Ok(());
+        }
+
+        let token = self.lexer.next().unwrap_or(Ok(Token::EOF));
+
+        self.value = self.lexer.take_value();
+        self.span = self.lexer.span();
+        self.origin = None;
+
+        match token {
+            Ok(token) => {
+                self.token = token;
+                Ok(())
+            }
+            Err(error) => {
+                self.token = Token::Invalid;
+                Err(error)
+            }
+        }
     }
 
-    fn next_line(&mut self) -> Result<()> {
+    fn next_line(&mut self) {
         while self.token_is(Token::EOL) {
-            self.next()?
+            if let Err(error) = self.next() {
+                self.recover(error);
+            }
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while !self.token_is(Token::EOL) && !self.token_is(Token::EOF) {
+            let _ = self.next();
+        }
+        self.next_line();
+    }
+
+    fn lexeme(&self) -> Lexeme {
+        Lexeme {
+            token: self.token,
+            value: self.value.clone(),
+            span: self.span,
+            origin: self.origin.clone(),
+        }
+    }
+
+    fn report(&mut self, error: MachinaError, span: Span) {
+        let notes = Origin::notes(&self.origin);
+        let _ = self.diagnostics.report_with_notes::<()>(error, span, notes);
+    }
+
+    // Reports the error at the current token and skips the rest of its line
+    fn recover(&mut self, error: MachinaError) {
+        self.report(error, self.span);
+
+        while !self.token_is(Token::EOL) && !self.token_is(Token::EOF) {
+            let _ = self.next();
         }
-        Ok(())
     }
 
     fn eat(&mut self, tkn: Token) -> Result<()> {
@@ -428,7 +1211,7 @@ impl<'a> Parser<'a> {
 
     fn take(&mut self, tkn: Token) -> Result<String> {
         let value = if self.token == tkn {
-            self.lexer.take_value().unwrap()
+            self.value.take().unwrap_or_default()
         } else {
             return Err(self.unexpected(&[tkn]));
         };
@@ -438,10 +1221,6 @@ impl<'a> Parser<'a> {
         Ok(value)
     }
 
-    fn line(&self) -> usize {
-        self.lexer.line()
-    }
-
     fn token_is(&self, tkn: Token) -> bool {
         self.token == tkn
     }
@@ -468,7 +1247,11 @@ impl<'a> Parser<'a> {
 #[derive(Debug, Clone)]
 pub struct PreFunction {
     name: String,
-    blocks: Vec<Block>
+    span: Span,
+    arity: Option<u8>,
+    locals: Option<u8>,
+    blocks: Vec<Block>,
+    aliases: HashMap<String, Register>,
 }
 
 #[derive(Debug, Clone)]
@@ -480,8 +1263,134 @@ struct Block {
 #[derive(Debug, Clone)]
 pub struct PreInstruction {
     pub opcode: OpCode,
-    pub line: usize,
-    pub operands: Vec<PreOperand>,
+    pub span: Span,
+    pub origin: Option<Origin>,
+    pub operands: Vec<(PreOperand, Span)>,
+}
+
+// The value of a constant expression, before it becomes an operand
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Integer(i64),
+
+    Float(f64),
+
+    String(String),
+
+    Char(char),
+
+    Boolean(bool),
+
+    Null,
+
+    // The name of an entry in the data section
+    Data(String),
+}
+
+impl Literal {
+
+    fn apply(self, operator: Token, rhs: Literal) -> Result<Literal> {
+        use Literal::*;
+
+        let value = match (self, rhs) {
+            (String(lhs), String(rhs)) if operator == Token::Plus => String(lhs + &rhs),
+            (Data(name), _) | (_, Data(name)) => {
+                return Err(MachinaError::InvalidExpression(format!("cannot apply `{}` to the data `{}`", operator, name)));
+            }
+            (String(_), _) | (_, String(_)) => {
+                return Err(MachinaError::InvalidExpression(format!("cannot apply `{}` to a string", operator)));
+            }
+            (Char(_), _) | (_, Char(_)) => {
+                return Err(MachinaError::InvalidExpression(format!("cannot apply `{}` to a char", operator)));
+            }
+            (Boolean(_), _) | (_, Boolean(_)) => {
+                return Err(MachinaError::InvalidExpression(format!("cannot apply `{}` to a boolean", operator)));
+            }
+            (Null, _) | (_, Null) => {
+                return Err(MachinaError::InvalidExpression(format!("cannot apply `{}` to null", operator)));
+            }
+            (Integer(_), Integer(0)) if operator == Token::Slash => {
+                return Err(MachinaError::InvalidExpression("division by zero".into()));
+            }
+            (Integer(lhs), Integer(rhs)) => {
+                let value = match operator {
+                    Token::Plus => lhs.checked_add(rhs),
+                    Token::Minus => lhs.checked_sub(rhs),
+                    Token::Star => lhs.checked_mul(rhs),
+                    _ => lhs.checked_div(rhs),
+                };
+
+                match value {
+                    Some(value) => Integer(value),
+                    None => return Err(MachinaError::InvalidExpression("integer overflow".into())),
+                }
+            }
+            (lhs, rhs) => {
+                let (lhs, rhs) = (lhs.as_float(), rhs.as_float());
+
+                Float(match operator {
+                    Token::Plus => lhs + rhs,
+                    Token::Minus => lhs - rhs,
+                    Token::Star => lhs * rhs,
+                    _ => lhs / rhs,
+                })
+            }
+        };
+
+        Ok(value)
+    }
+
+    fn as_float(&self) -> f64 {
+        match self {
+            Literal::Integer(value) => *value as f64,
+            Literal::Float(value) => *value,
+            _ => f64::NAN,
+        }
+    }
+
+    fn into_operand(self) -> PreOperand {
+        match self {
+            Literal::Integer(value) => PreOperand::Integer(value),
+            Literal::Float(value) => PreOperand::Float(value),
+            Literal::String(value) => PreOperand::String(value),
+            Literal::Char(value) => PreOperand::Char(value),
+            Literal::Boolean(value) => PreOperand::Boolean(value),
+            Literal::Null => PreOperand::Null,
+            Literal::Data(name) => PreOperand::Data(name),
+        }
+    }
+}
+
+// The macro call a token was expanded from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Origin {
+    pub name: String,
+    pub span: Span,
+    pub depth: usize,
+}
+
+impl Origin {
+
+    fn notes(origin: &Option<Origin>) -> Vec<(String, Span)> {
+        origin
+            .iter()
+            .map(|origin| (format!("in expansion of macro `{}`", origin.name), origin.span))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Lexeme {
+    token: Token,
+    value: Option<String>,
+    span: Span,
+    origin: Option<Origin>,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Lexeme>,
 }
 
 #[derive(Debug, Clone)]
@@ -490,11 +1399,556 @@ pub enum PreOperand {
 
     String(String),
 
-    Number(String),
+    Integer(i64),
+
+    Float(f64),
+
+    Char(char),
+
+    Boolean(bool),
+
+    Null,
 
     Register(String),
 
     Function(String),
 
-    Label(String)
+    Label(String),
+
+    Data(String),
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(source: &str) -> Vec<MachinaError> {
+        Parser::new(source).parse().unwrap_err().errors().cloned().collect()
+    }
+
+    #[test]
+    fn parse_module() {
+        let module = Parser::new(r#"
+            @entrypoint
+              MOVE      %0, 1
+              CALL      @double, %0, %0, %0
+              RET       %0
+
+            @double
+              ADD       %0, %0
+              RET       %0
+        "#).parse().unwrap();
+
+        assert_eq!(module.functions.len(), 2);
+        assert_eq!(module.function("double"), Some(1));
+        assert_eq!(module.functions[0].instructions()[1].get(0), Operand::Function(1));
+    }
+
+    #[test]
+    fn source_map() {
+        let module = Parser::new("@entrypoint\n  MOVE %0, 1\n\n.L0\n  ADD %0, 1\n  JLT .L0, %0, 10\n  RET %0\n")
+            .with_file("loop.machina")
+            .parse()
+            .unwrap();
+
+        let function = &module.functions[0];
+
+        assert_eq!(function.name, "entrypoint");
+        assert_eq!(function.source_map.line(0), Some(2));
+        assert_eq!(function.source_map.line(1), Some(5));
+        assert_eq!(function.source_map.line(2), Some(6));
+        assert_eq!(function.location(3), Some("loop.machina:7".into()));
+    }
+
+    #[test]
+    fn collect_every_error() {
+        let errors = errors(r#"
+            @entrypoint
+              MOVE      1, 2
+              MOVE      %0, $
+              JMP       .missing
+              RET       %0
+        "#);
+
+        assert_eq!(errors, vec![
+            MachinaError::Expected("`register`".into(), "number".into()),
+            MachinaError::InvalidCharacter('$'),
+            MachinaError::TargetNotFound("missing".into()),
+        ]);
+    }
+
+    #[test]
+    fn recover_before_first_function() {
+        let errors = errors(r#"
+            MOVE      %0, 1
+            MOVE      %0, 2
+
+            @entrypoint
+              FOO       %0
+              RET       %0
+        "#);
+
+        assert_eq!(errors, vec![
+            MachinaError::Expected("`function`".into(), "move".into()),
+            MachinaError::InvalidInstruction("FOO".into()),
+        ]);
+    }
+
+    #[test]
+    fn render_errors() {
+        let source = "@entrypoint\n  MOVE  %0, 1\n  JMP   .nowhere\n";
+
+        let diagnostics = Parser::new(source).parse().unwrap_err();
+
+        assert_eq!(diagnostics.render("test.machina", source), [
+            "error: Target with label `nowhere` not found",
+            " --> test.machina:3:9",
+            "  |",
+            "3 |   JMP   .nowhere",
+            "  |         ^^^^^^^^",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn expand_macros() {
+        let module = Parser::new(r#"
+            %macro jdiv(target, value, divisor, tmp)
+              MOVE      tmp, value
+              MOD       tmp, divisor
+              JEQ       target, tmp, 0
+            %endmacro
+
+            %macro countdown(reg)
+            .loop
+              SUB       reg, 1
+              JGT       .loop, reg, 0
+            %endmacro
+
+            @entrypoint
+              jdiv      .done, %0, 3, %1
+              countdown %0
+              countdown %1
+            .done
+              RET       %0
+        "#).parse().unwrap();
+
+        let instructions = &module.functions[0].instructions();
+
+        assert_eq!(instructions.len(), 8);
+        assert_eq!(instructions[0], Instruction::new(OpCode::Move, [
+            Operand::Register(1), Operand::Register(0), Operand::None, Operand::None,
+        ]));
+        assert_eq!(instructions[1].get(1), Operand::Immediate(3));
+        assert_eq!(instructions[2].get(0), Operand::Position(7));
+        assert_eq!(instructions[4].get(0), Operand::Position(3));
+        assert_eq!(instructions[6].get(0), Operand::Position(5));
+        assert_eq!(module.functions[0].source_map.line(1), Some(15));
+    }
+
+    #[test]
+    fn macro_errors() {
+        let errors = errors(r#"
+            %macro pair(a, b)
+              MOVE      a, b
+            %endmacro
+
+            @entrypoint
+              pair      %0
+              pair      1, 2
+              RET       %0
+        "#);
+
+        assert_eq!(errors, vec![
+            MachinaError::MacroArguments("pair".into(), 2, 1),
+            MachinaError::Expected("`register`".into(), "number".into()),
+        ]);
+    }
+
+    #[test]
+    fn macro_recursion() {
+        let errors = errors(r#"
+            %macro twice(a)
+              twice     a
+              twice     a
+            %endmacro
+
+            @entrypoint
+              twice     %0
+              RET       %0
+        "#);
+
+        assert_eq!(errors, vec![MachinaError::MacroRecursion("twice".into())]);
+
+        // Only nesting counts, not how many times a macro is used
+        let source = format!(r#"
+            %macro bump(a)
+              ADD       a, 1
+            %endmacro
+
+            @entrypoint
+              {}
+              RET       %0
+        "#, "bump %0\n".repeat(20_000));
+
+        let module = Parser::new(&source).parse().unwrap();
+
+        assert_eq!(module.functions[0].instructions().len(), 20_001);
+    }
+
+    #[test]
+    fn named_constants() {
+        let module = Parser::new(r#"
+            .define SIZE = 10
+            .equ    GREETING, "hello, " + "world"
+
+            @entrypoint
+              .define HALF = SIZE / 2
+              MOVE      %0, SIZE * 2+1
+              MOVE      %1, -(HALF - 1) * 1.5
+              MOVE      %2, GREETING
+              RET       %0
+
+            @other
+              .define SIZE = 3
+              MOVE      %0, SIZE
+              RET       %0
+        "#).parse().unwrap();
+
+        let entrypoint = &module.functions[0].instructions();
+        let other = &module.functions[1].instructions();
+
+        assert_eq!(entrypoint[0].get(1), Operand::Immediate(21));
+        assert_eq!(entrypoint[1].get(1), Operand::Constant(0));
+        assert_eq!(entrypoint[2].get(1), Operand::Constant(1));
+        assert_eq!(module.constants[0], Constant::Number((-6.0).into()));
+        assert_eq!(module.constants[1], Constant::String("hello, world".into()));
+        assert_eq!(other[0].get(1), Operand::Immediate(3));
+    }
+
+    #[test]
+    fn constant_errors() {
+        let errors = errors(r#"
+            .define A = 1
+            .define A = 2
+
+            @entrypoint
+              .define B = 1
+              MOVE      %0, C
+              MOVE      %0, 1 / 0
+              MOVE      %0, "a" * 2
+              RET       %0
+
+            @other
+              MOVE      %0, B
+              RET       %0
+        "#);
+
+        assert_eq!(errors, vec![
+            MachinaError::ConstantAlreadyDefined("A".into()),
+            MachinaError::ConstantNotFound("C".into()),
+            MachinaError::InvalidExpression("division by zero".into()),
+            MachinaError::InvalidExpression("cannot apply `*` to a string".into()),
+            MachinaError::ConstantNotFound("B".into()),
+        ]);
+    }
+
+    #[test]
+    fn register_aliases() {
+        let module = Parser::new(r#"
+            @entrypoint
+              .reg total = %1
+              MOVE      %total, 0
+              ADD       %total, %0
+              RET       %total
+
+            @other
+              .reg total = %2
+              .reg TOTAL = %2
+              RET       %total
+        "#).parse().unwrap();
+
+        let entrypoint = &module.functions[0].instructions();
+
+        assert_eq!(entrypoint[0].get(0), Operand::Register(1));
+        assert_eq!(entrypoint[1].get(0), Operand::Register(1));
+        assert_eq!(entrypoint[1].get(1), Operand::Register(0));
+        assert_eq!(module.functions[1].instructions()[0].get(0), Operand::Register(2));
+    }
+
+    #[test]
+    fn register_alias_errors() {
+        let errors = errors(r#"
+            @entrypoint
+              .reg total = %1
+              .reg total = %2
+              .reg other = %name
+              MOVE      %count, 0
+              RET       %total
+
+            @other
+              RET       %total
+        "#);
+
+        assert_eq!(errors, vec![
+            MachinaError::RegisterAliasConflict("total".into(), 1),
+            MachinaError::InvalidRegister("name".into()),
+            MachinaError::RegisterAliasNotFound("count".into()),
+            MachinaError::RegisterAliasNotFound("total".into()),
+        ]);
+    }
+
+    #[test]
+    fn register_alias_bounds() {
+        let errors = errors(r#"
+            @entrypoint
+              .reg big = %300
+              .reg last = %254
+              MOVE      %big, 1
+              MOVE      %last, %big
+              RET       %last
+        "#);
+
+        assert_eq!(errors, vec![MachinaError::InvalidRegister("300".into())]);
+    }
+
+    #[test]
+    fn function_signatures() {
+        let module = Parser::new(r#"
+            @entrypoint
+              MOVE      %7, 1
+              CALL      @none, %0, %0, %0
+              CALL      @fib, %7, %7, %7
+              RET       %0
+
+            @none()
+              RET
+
+            @fib(1) locals 3
+              RET       %0
+        "#).parse().unwrap();
+
+        let signatures = module.functions.iter()
+            .map(|function| (function.arity, function.locals))
+            .collect::<Vec<_>>();
+
+        assert_eq!(signatures, vec![(None, 8), (Some(0), 0), (Some(1), 3)]);
+    }
+
+    #[test]
+    fn signature_errors() {
+        let errors = errors(r#"
+            @entrypoint
+              CALL      @pair, %0, %0, %2
+              SPAWN     @pair, %0, %0, %0
+              RET       %0
+
+            @pair(2) locals 1
+              RET       %0
+
+            @wide locals 300
+              RET       %0
+        "#);
+
+        assert_eq!(errors, vec![
+            MachinaError::InvalidCount("300".into()),
+            MachinaError::ArityMismatch("pair".into(), 2, 3),
+            MachinaError::ArityMismatch("pair".into(), 2, 1),
+            MachinaError::TooFewLocals("pair".into(), 1, 2),
+        ]);
+    }
+
+    #[test]
+    fn extern_declarations() {
+        let module = Parser::new(r#"
+            import "lib.machina"
+            .extern @double
+            .extern @double
+
+            @entrypoint
+              CALL      @double, %0, %0, %0
+              CALL      @entrypoint, %0, %0, %0
+              RET       %0
+        "#).parse().unwrap();
+
+        assert_eq!(module.externs, vec!["double".to_string()]);
+        assert_eq!(module.functions[0].instructions()[0].get(0), Operand::Function(1));
+        assert_eq!(module.functions[0].instructions()[1].get(0), Operand::Function(0));
+
+        let errors = errors(r#"
+            import "lib.machina"
+
+            @entrypoint
+              CALL      @doubel, %0, %0, %0
+              RET       %0
+        "#);
+
+        assert_eq!(errors, vec![MachinaError::FunctionNotFound("doubel".into())]);
+    }
+
+    #[test]
+    fn data_section() {
+        let module = Parser::new(r#"
+            .define SIZE = 4
+
+            .data
+              table  = [1, 2.5, "three", [SIZE]]
+              buffer = bytes SIZE
+              raw    = bytes [0, 255]
+
+            @entrypoint
+              MOVE      %0, table
+              MOVE      %1, table
+              MOVE      %2, buffer
+              RET       %0
+        "#).parse().unwrap();
+
+        let instructions = &module.functions[0].instructions();
+
+        assert_eq!(instructions[0].get(1), Operand::Constant(0));
+        assert_eq!(instructions[1].get(1), Operand::Constant(0));
+        assert_eq!(instructions[2].get(1), Operand::Constant(1));
+        assert_eq!(module.constants, vec![
+            Constant::List(vec![
+                Constant::Integer(1),
+                Constant::Number(2.5.into()),
+                Constant::String("three".into()),
+                Constant::List(vec![Constant::Integer(4)]),
+            ]),
+            Constant::Bytes(vec![0; 4]),
+        ]);
+    }
+
+    #[test]
+    fn three_address_instructions() {
+        let module = Parser::new(r#"
+            @entrypoint
+              ADD       %0, %1, 2
+              SUB       %0, 10, %1
+              LT        %2, %0, "k"
+              MUL       %0, %1
+              RET       %0
+        "#).parse().unwrap();
+
+        let operands = module.functions[0].instructions()
+            .iter()
+            .map(|instruction| instruction.operands)
+            .collect::<Vec<_>>();
+
+        assert_eq!(operands[..4], [
+            [Operand::Register(0), Operand::Register(1), Operand::Immediate(2), Operand::None],
+            [Operand::Register(0), Operand::Immediate(10), Operand::Register(1), Operand::None],
+            [Operand::Register(2), Operand::Register(0), Operand::Constant(0), Operand::None],
+            [Operand::Register(0), Operand::Register(1), Operand::None, Operand::None],
+        ]);
+
+        assert_eq!(errors("@entrypoint\n  LEN %0, %1, %2\n  RET\n"), vec![
+            MachinaError::Expected("`instruction`".into(), ",".into()),
+        ]);
+    }
+
+    #[test]
+    fn intern_constants() {
+        let module = Parser::new(r#"
+            @entrypoint
+              MOVE      %0, "hello"
+              MOVE      %1, 1.5
+              MOVE      %2, "hello"
+              MOVE      %3, 1.5
+              MOVE      %4, 2.0
+              MOVE      %5, 3000000000
+              MOVE      %6, 'a'
+              MOVE      %7, true
+              MOVE      %8, null
+              RET       %0
+        "#).parse().unwrap();
+
+        let operands = module.functions[0].instructions()
+            .iter()
+            .map(|instruction| instruction.get(1))
+            .collect::<Vec<_>>();
+
+        assert_eq!(operands[..9], [
+            Operand::Constant(0),
+            Operand::Constant(1),
+            Operand::Constant(0),
+            Operand::Constant(1),
+            Operand::Constant(2),
+            Operand::Constant(3),
+            Operand::Constant(4),
+            Operand::Constant(5),
+            Operand::Constant(6),
+        ]);
+        assert_eq!(module.constants, vec![
+            Constant::String("hello".into()),
+            Constant::Number(1.5.into()),
+            Constant::Number(2.0.into()),
+            Constant::Integer(3_000_000_000),
+            Constant::Char('a'),
+            Constant::Boolean(true),
+            Constant::Null,
+        ]);
+    }
+
+    #[test]
+    fn wide_constants() {
+        let count = u16::MAX as usize + 2;
+
+        let mut source = String::from("@entrypoint\n");
+
+        for index in 0..count {
+            source.push_str(&format!("  MOVE %0, \"{}\"\n", index));
+        }
+
+        source.push_str("  RET %0\n");
+
+        let module = Parser::new(&source).parse().unwrap();
+        let instructions = &module.functions[0].instructions();
+
+        assert_eq!(module.constants.len(), count);
+        assert_eq!(instructions[u16::MAX as usize].get(1), Operand::Constant(u16::MAX));
+        assert_eq!(instructions[count - 1].get(1), Operand::WideConstant(count as u32 - 1));
+    }
+
+    #[test]
+    fn data_errors() {
+        let errors = errors(r#"
+            .data
+              table = [1, 2]
+              table = [3]
+              raw   = bytes [256]
+
+            @entrypoint
+              MOVE      %0, table + 1
+              RET       %0
+        "#);
+
+        assert_eq!(errors, vec![
+            MachinaError::ConstantAlreadyDefined("table".into()),
+            MachinaError::InvalidExpression("bytes must be integers between 0 and 255".into()),
+            MachinaError::InvalidExpression("cannot apply `+` to the data `table`".into()),
+        ]);
+    }
+
+    #[test]
+    fn render_macro_errors() {
+        let source = "%macro go()\n  JMP .nowhere\n%endmacro\n@entrypoint\n  go\n";
+
+        let diagnostics = Parser::new(source).parse().unwrap_err();
+
+        assert_eq!(diagnostics.render("test.machina", source), [
+            "error: Target with label `nowhere` not found",
+            " --> test.machina:2:7",
+            "  |",
+            "2 |   JMP .nowhere",
+            "  |       ^^^^^^^^",
+            "note: in expansion of macro `go`",
+            " --> test.machina:5:3",
+            "  |",
+            "5 |   go",
+            "  |   ^^",
+            "",
+        ].join("\n"));
+    }
 }
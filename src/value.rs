@@ -1,5 +1,6 @@
 use std::{cmp::Ordering, fmt::{Debug, Display}};
 
+const TAG_MASK: u64 = 0xffff000000000000;
 const MAX_NUM:  u64 = 0xfff8000000000000;
 const NAN_TAG:  u64 = MAX_NUM;
 const INT_TAG:  u64 = 0xfff9000000000000;
@@ -9,7 +10,7 @@ const TRUE_TAG: u64 = 0xfffc000000000000;
 const FLSE_TAG: u64 = 0xfffd000000000000;
 const NULL_TAG: u64 = 0xffff000000000000;
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Value(u64);
 
 const TRUE:  Value = Value(TRUE_TAG);
@@ -26,7 +27,7 @@ impl Value {
 
     #[inline(always)]
     pub fn is_int(&self) -> bool {
-        (self.0 & INT_TAG) == INT_TAG
+        (self.0 & TAG_MASK) == INT_TAG
     }
 
     #[inline(always)]
@@ -36,7 +37,7 @@ impl Value {
 
     #[inline(always)]
     pub fn is_char(&self) -> bool {
-        (self.0 & CHR_TAG) == CHR_TAG
+        (self.0 & TAG_MASK) == CHR_TAG
     }
 
     #[inline(always)]
@@ -56,7 +57,26 @@ impl Value {
 
     #[inline(always)]
     pub fn is_ptr(&self) -> bool {
-        (self.0 & PTR_TAG) == PTR_TAG
+        (self.0 & TAG_MASK) == PTR_TAG
+    }
+
+    // Objects are named by the machine that owns them, see `Machina::type_name`
+    pub fn type_name(&self) -> String {
+        if self.is_num() {
+            "number".into()
+        } else if self.is_int() {
+            "integer".into()
+        } else if self.is_char() {
+            "char".into()
+        } else if self.is_ptr() {
+            "object".into()
+        } else if self.is_null() {
+            "null".into()
+        } else if self.is_true() || self.is_false() {
+            "boolean".into()
+        } else {
+            "nan".into()
+        }
     }
 
     #[inline(always)]
@@ -245,7 +265,7 @@ impl From<i64> for Value {
 
     #[inline(always)]
     fn from(i: i64) -> Value {
-        if i <= i32::MAX as i64 {
+        if i >= i32::MIN as i64 && i <= i32::MAX as i64 {
             Value::from(i as i32)
         } else {
             Value((i as f64).to_bits())
@@ -257,7 +277,7 @@ impl From<i32> for Value {
 
     #[inline(always)]
     fn from(i: i32) -> Value {
-        Value(INT_TAG | i as u64)
+        Value(INT_TAG | (i as u32) as u64)
     }
 }
 
@@ -335,6 +355,8 @@ mod tests {
         let a = Value::from(-321);
         assert!(a.is_int());
         assert_eq!(a.get_int_unchecked(), -321);
+        assert!(!a.is_null());
+        assert!(!a.is_ptr());
     }
 
     #[test]
@@ -355,6 +377,17 @@ mod tests {
         assert_eq!(d, *val);
         assert!(d.is_int());
         assert_eq!(d.get_int_unchecked(), 42);
+        assert!(!p.is_int());
+        assert!(!p.is_char());
+        assert_eq!(p.to_string(), format!("0x{:08X}", val_ptr as u64));
+    }
+
+    #[test]
+    fn tags() {
+        assert!(!Value::null().is_int());
+        assert!(!Value::null().is_ptr());
+        assert!(!Value::from(false).is_int());
+        assert!(!Value::from(true).is_char());
     }
 
     #[test]
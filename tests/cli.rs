@@ -0,0 +1,222 @@
+use std::{
+    env,
+    fs,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+fn source(name: &str, source: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("machina-cli-{}-{}.machina", name, std::process::id()));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+fn machina(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_machina"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn arguments_and_exit_status() {
+    let path = source("args", r#"
+        @entrypoint
+          WRITE     %1
+          ADD       %0, 2
+          RET       %0
+    "#);
+
+    let output = machina(&[path.to_str().unwrap(), "40", "hello"]);
+    let _ = fs::remove_file(&path);
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hello\n");
+    assert_eq!(output.status.code(), Some(42));
+}
+
+#[test]
+fn exit_status_defaults_to_zero() {
+    let path = source("status", r#"
+        @entrypoint
+          MOVE      %0, "done"
+          RET       %0
+    "#);
+
+    let output = machina(&[path.to_str().unwrap()]);
+    let _ = fs::remove_file(&path);
+
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn entry_point_by_name() {
+    let path = source("entry", r#"
+        @helper
+          RET       %0
+
+        @entrypoint
+          WRITE     "main"
+          RET
+
+        @other
+          WRITE     "other"
+          RET
+    "#);
+
+    let default = machina(&[path.to_str().unwrap()]);
+    let other = machina(&["--entry", "@other", path.to_str().unwrap()]);
+    let missing = machina(&["--entry", "missing", path.to_str().unwrap()]);
+    let _ = fs::remove_file(&path);
+
+    assert_eq!(String::from_utf8_lossy(&default.stdout), "main\n");
+    assert_eq!(String::from_utf8_lossy(&other.stdout), "other\n");
+    assert_eq!(String::from_utf8_lossy(&missing.stderr), "Entry point `@missing` not found\n");
+    assert_eq!(missing.status.code(), Some(1));
+}
+
+#[test]
+fn optimize_flag() {
+    let path = source("optimize", r#"
+        @entrypoint
+          MOVE      %1, 40
+          ADD       %1, 2
+          JMP       .L0
+          WRITE     "unreachable"
+        .L0
+          WRITE     %1
+          RET       %1
+    "#);
+
+    let output = machina(&["-O", "--entry", "entrypoint", path.to_str().unwrap()]);
+    let _ = fs::remove_file(&path);
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "42\n");
+    assert_eq!(output.status.code(), Some(42));
+}
+
+#[test]
+fn imports_are_linked() {
+    let library = source("library", r#"
+        @double
+          ADD       %0, %0
+          RET       %0
+    "#);
+
+    let name = library.file_name().unwrap().to_str().unwrap();
+
+    let path = source("import", &format!(r#"
+        import "{}"
+        .extern @double
+
+        @entrypoint
+          MOVE      %0, 21
+          CALL      @double, %0, %0, %0
+          RET       %0
+    "#, name));
+
+    let output = machina(&[path.to_str().unwrap()]);
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(&library);
+
+    assert_eq!(output.status.code(), Some(42));
+}
+
+#[test]
+fn build_and_link_objects() {
+    let library = source("objlib", r#"
+        @double
+          ADD       %0, %0
+          RET       %0
+    "#);
+
+    let path = source("objmain", r#"
+        .extern @double
+
+        @entrypoint
+          MOVE      %0, 21
+          CALL      @double, %0, %0, %0
+          RET       %0
+    "#);
+
+    let library_object = library.with_extension("mo");
+    let main_object = path.with_extension("mo");
+    let program = path.with_extension("out.mo");
+
+    let first = machina(&["build", library.to_str().unwrap()]);
+    let second = machina(&["build", path.to_str().unwrap(), "-o", main_object.to_str().unwrap()]);
+    let linked = machina(&["link", main_object.to_str().unwrap(), library_object.to_str().unwrap(), "-o", program.to_str().unwrap()]);
+    let unresolved = machina(&["link", main_object.to_str().unwrap(), "-o", program.with_extension("bad").to_str().unwrap()]);
+    let output = machina(&[program.to_str().unwrap()]);
+
+    for file in [&library, &path, &library_object, &main_object, &program] {
+        let _ = fs::remove_file(file);
+    }
+
+    assert_eq!(first.status.code(), Some(0));
+    assert_eq!(second.status.code(), Some(0));
+    assert_eq!(linked.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&unresolved.stderr),
+        format!("error: Function with name `double` used in `{}` is not defined by any module\n", path.display())
+    );
+    assert_eq!(output.status.code(), Some(42));
+}
+
+#[test]
+fn build_to_c() {
+    let library = source("clib", r#"
+        @double
+          ADD       %0, %0
+          RET       %0
+    "#);
+
+    let name = library.file_name().unwrap().to_str().unwrap();
+
+    let path = source("cmain", &format!(r#"
+        import "{}"
+        .extern @double
+
+        @entrypoint
+          MOVE      %0, 21
+          CALL      @double, %0, %0, %0
+          WRITE     %0
+          RET       %0
+    "#, name));
+
+    let missing = source("cmissing", r#"
+        @other
+          RET
+    "#);
+
+    let code = path.with_extension("c");
+    let binary = path.with_extension("bin");
+
+    let built = machina(&["build", "--emit", "c", path.to_str().unwrap()]);
+    let unknown = machina(&["build", "--emit", "asm", path.to_str().unwrap()]);
+    let entry = machina(&["build", "--emit", "c", missing.to_str().unwrap(), "-o", code.with_extension("bad").to_str().unwrap()]);
+
+    let compiled = Command::new("cc")
+        .args(["-O2", "-std=c99", "-pthread", "-o"])
+        .arg(&binary)
+        .arg(&code)
+        .arg("-lm")
+        .status();
+
+    let output = Command::new(&binary).output();
+
+    for file in [&library, &path, &missing, &code, &binary] {
+        let _ = fs::remove_file(file);
+    }
+
+    assert_eq!(built.status.code(), Some(0));
+    assert_eq!(unknown.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&entry.stderr), "Entry point `@entrypoint` not found\n");
+    assert_eq!(entry.status.code(), Some(1));
+
+    // Only when there is a C compiler around
+    if compiled.is_ok_and(|status| status.success()) {
+        let output = output.unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "42\n");
+        assert_eq!(output.status.code(), Some(42));
+    }
+}
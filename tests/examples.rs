@@ -0,0 +1,176 @@
+use std::{
+    env,
+    fs,
+    io::{Cursor, Write},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use machina::{
+    actor,
+    aot,
+    bytecode::Module,
+    io::{Input, Output},
+    machina::{Environment, Machina},
+    optimizer::Optimizer,
+    parser::Parser,
+};
+
+#[cfg_attr(not(feature = "jit"), allow(unused_variables))]
+fn run(path: &Path, optimize: bool, jit: Option<u32>) -> String {
+    let source = fs::read_to_string(path).unwrap();
+
+    let mut module = Parser::new(&source).parse().unwrap();
+
+    if optimize {
+        Optimizer::default().optimize(&mut module);
+    }
+
+    let entry = module.function("entrypoint").unwrap();
+
+    let Module { functions, constants, .. } = module;
+
+    let environment = Environment::new(functions, constants);
+
+    let input = fs::read_to_string(path.with_extension("in")).unwrap_or_default();
+
+    let output = Output::buffer();
+
+    let machina = Machina::new(&environment)
+        .with_input(Input::new(Cursor::new(input)))
+        .with_output(output.clone());
+
+    #[cfg(feature = "jit")]
+    let machina = machina.with_jit(jit);
+
+    actor::run(machina, entry, vec![]).unwrap();
+
+    output.contents().unwrap()
+}
+
+// Compiles the C translation of the program with the system compiler, and
+// runs it. Nothing when there is no compiler to use
+fn native(path: &Path, optimize: bool) -> Option<String> {
+    let source = fs::read_to_string(path).unwrap();
+
+    let mut module = Parser::new(&source).parse().unwrap();
+
+    if optimize {
+        Optimizer::default().optimize(&mut module);
+    }
+
+    let entry = module.function("entrypoint").unwrap();
+
+    let name = format!("machina-aot-{}-{}-{}", path.file_stem().unwrap().to_string_lossy(), optimize, std::process::id());
+    let code = env::temp_dir().join(&name).with_extension("c");
+    let binary = env::temp_dir().join(&name);
+
+    fs::write(&code, aot::emit(&module, entry)).unwrap();
+
+    let compiled = Command::new(env::var("CC").unwrap_or_else(|_| "cc".into()))
+        .args(["-O2", "-std=c99", "-Wall", "-Werror", "-pthread", "-o"])
+        .arg(&binary)
+        .arg(&code)
+        .arg("-lm")
+        .status();
+
+    let _ = fs::remove_file(&code);
+
+    match compiled {
+        Ok(status) => assert!(status.success(), "the C translation of {} doesn't compile", path.display()),
+        Err(_) => return None,
+    }
+
+    let input = fs::read(path.with_extension("in")).unwrap_or_default();
+
+    let mut child = Command::new(&binary)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(&input).unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    let _ = fs::remove_file(&binary);
+
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn golden(name: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples").join(name);
+
+    let expected = fs::read_to_string(path.with_extension("out")).unwrap();
+
+    // The optimizer must never change what a program prints
+    assert_eq!(run(&path.with_extension("machina"), false, None), expected);
+    assert_eq!(run(&path.with_extension("machina"), true, None), expected);
+
+    // Nor compiling it ahead of time into C
+    for &optimize in &[false, true] {
+        if let Some(output) = native(&path.with_extension("machina"), optimize) {
+            assert_eq!(output, expected);
+        }
+    }
+
+    // And neither must the jit, compiling everything up front or only once
+    // called or looped twice
+    #[cfg(feature = "jit")]
+    for &threshold in &[0, 2] {
+        assert_eq!(run(&path.with_extension("machina"), false, Some(threshold)), expected);
+        assert_eq!(run(&path.with_extension("machina"), true, Some(threshold)), expected);
+    }
+}
+
+#[test]
+fn actors() {
+    golden("actors");
+}
+
+#[test]
+fn collatz() {
+    golden("collatz");
+}
+
+#[test]
+fn data() {
+    golden("data");
+}
+
+#[test]
+fn euler_01() {
+    golden("euler_01");
+}
+
+#[test]
+fn fibonacci() {
+    golden("fibonacci");
+}
+
+#[test]
+fn fizzbuzz() {
+    golden("fizzbuzz");
+}
+
+#[test]
+fn floats() {
+    golden("floats");
+}
+
+#[test]
+fn sum() {
+    golden("sum");
+}
+
+#[test]
+fn every_example_has_a_golden_output() {
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+
+    for entry in fs::read_dir(&examples).unwrap() {
+        let path = entry.unwrap().path();
+
+        if path.extension().is_some_and(|ext| ext == "machina") {
+            assert!(path.with_extension("out").exists(), "missing {}", path.with_extension("out").display());
+        }
+    }
+}